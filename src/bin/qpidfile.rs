@@ -0,0 +1,372 @@
+//! Command line interface to the pidfile operations.
+//!
+//! The exit codes of `status` and `pid` follow the LSB init script
+//! conventions; `0` if the program is running, `1` if it is dead but the
+//! pidfile exists, `3` if it is not running and `4` if its status is unknown.
+//! The other commands exit with `0` on success, `1` on failure and `2` on
+//! invalid usage.
+use std::ffi::OsString;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::time::Duration;
+
+use qpidfile::{Error, Pidfile, PidfileInfo, RunOptions, Signal};
+use qpidfile::{StopOutcome, StopPolicy};
+
+const USAGE: &str = "\
+usage: qpidfile <command> [options] [--pidfile] <file> [-- <cmd> [args]]
+
+commands:
+  status       report whether the process named in the pidfile is running
+  pid          print the pid stored in the pidfile
+  signal       send a signal to the process named in the pidfile
+  stop         terminate the process named in the pidfile
+  wait         wait for the process named in the pidfile to exit
+  clean-stale  remove the pidfile if the process named in it is gone
+  run          run a command while holding the pidfile
+
+options:
+  -p, --pidfile <file>   the pidfile
+  -s, --signal <sig>     signal to send, by name or number (default: TERM)
+  -t, --timeout <secs>   how long to wait for the process to exit
+      --no-kill          do not escalate to SIGKILL when stopping
+      --own-pid          run: write our own pid rather than the command's
+  -h, --help             show this help
+";
+
+/// LSB status codes.
+const STATUS_RUNNING: i32 = 0;
+const STATUS_DEAD: i32 = 1;
+const STATUS_NOT_RUNNING: i32 = 3;
+const STATUS_UNKNOWN: i32 = 4;
+
+/// Exit codes of the commands which are not status queries.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+/// Parsed command line arguments.
+struct Args {
+  cmd: String,
+  pidfile: PathBuf,
+  signal: Option<Signal>,
+  timeout: Option<Duration>,
+  kill: bool,
+  own_pid: bool,
+  argv: Vec<OsString>
+}
+
+fn main() {
+  let args = match parse_args(std::env::args_os().skip(1)) {
+    Ok(Some(args)) => args,
+    Ok(None) => {
+      print!("{}", USAGE);
+      process::exit(0);
+    }
+    Err(msg) => {
+      eprintln!("qpidfile: {}", msg);
+      eprint!("{}", USAGE);
+      process::exit(EXIT_USAGE);
+    }
+  };
+
+  let code = match args.cmd.as_str() {
+    "status" => status(&args, false),
+    "pid" => status(&args, true),
+    "signal" => signal(&args),
+    "stop" => stop(&args),
+    "wait" => wait(&args),
+    "clean-stale" => clean_stale(&args),
+    "run" => run(&args),
+    cmd => {
+      eprintln!("qpidfile: unknown command {:?}", cmd);
+      EXIT_USAGE
+    }
+  };
+  process::exit(code);
+}
+
+/// Parse the command line.  Returns `Ok(None)` if help was requested.
+fn parse_args<I>(mut it: I) -> Result<Option<Args>, String>
+where
+  I: Iterator<Item = OsString>
+{
+  let mut cmd = None;
+  let mut pidfile = None;
+  let mut signal = None;
+  let mut timeout = None;
+  let mut kill = true;
+  let mut own_pid = false;
+  let mut argv = Vec::new();
+
+  while let Some(arg) = it.next() {
+    let s = arg.to_string_lossy();
+    match s.as_ref() {
+      "-h" | "--help" => return Ok(None),
+      "--" => {
+        argv.extend(&mut it);
+        break;
+      }
+      "-p" | "--pidfile" => {
+        pidfile = Some(PathBuf::from(value(&mut it, &s)?));
+      }
+      "-s" | "--signal" => {
+        let v = value(&mut it, &s)?;
+        let v = v.to_string_lossy();
+        signal = Some(
+          Signal::from_name(&v)
+            .ok_or_else(|| format!("unknown signal {:?}", v))?
+        );
+      }
+      "-t" | "--timeout" => {
+        let v = value(&mut it, &s)?;
+        let v = v.to_string_lossy();
+        let secs = v
+          .parse::<f64>()
+          .ok()
+          .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+          .ok_or_else(|| format!("invalid timeout {:?}", v))?;
+        timeout = Some(secs);
+      }
+      "--no-kill" => kill = false,
+      "--own-pid" => own_pid = true,
+      _ if s.starts_with('-') && s.len() > 1 => {
+        return Err(format!("unknown option {:?}", s));
+      }
+      _ if cmd.is_none() => cmd = Some(s.into_owned()),
+      _ if pidfile.is_none() => pidfile = Some(PathBuf::from(arg)),
+      _ => return Err(format!("unexpected argument {:?}", s))
+    }
+  }
+
+  let cmd = cmd.ok_or("missing command")?;
+  let pidfile = pidfile.ok_or("missing pidfile")?;
+  if cmd == "run" && argv.is_empty() {
+    return Err("missing command to run".to_string());
+  }
+  if cmd != "run" && !argv.is_empty() {
+    return Err(format!("{} does not take a command to run", cmd));
+  }
+  Ok(Some(Args {
+    cmd,
+    pidfile,
+    signal,
+    timeout,
+    kill,
+    own_pid,
+    argv
+  }))
+}
+
+/// Get the value of the option `opt`.
+fn value<I>(it: &mut I, opt: &str) -> Result<OsString, String>
+where
+  I: Iterator<Item = OsString>
+{
+  it.next().ok_or_else(|| format!("{} requires a value", opt))
+}
+
+/// Report the status of the process named in the pidfile, or if `pid_only`
+/// is set print its pid.
+fn status(args: &Args, pid_only: bool) -> i32 {
+  let info = match PidfileInfo::read(&args.pidfile) {
+    Ok(info) => info,
+    Err(Error::Missing(_)) => {
+      if !pid_only {
+        println!("not running");
+      }
+      return STATUS_NOT_RUNNING;
+    }
+    Err(e) => {
+      eprintln!("qpidfile: {}", e);
+      return STATUS_UNKNOWN;
+    }
+  };
+  let alive = match info.is_alive() {
+    Ok(alive) => alive,
+    Err(e) => {
+      eprintln!("qpidfile: {}", e);
+      return STATUS_UNKNOWN;
+    }
+  };
+  if pid_only {
+    println!("{}", info.pid());
+  } else if alive {
+    println!("running (pid {})", info.pid());
+  } else {
+    println!("dead, but pidfile exists (pid {})", info.pid());
+  }
+  if alive {
+    STATUS_RUNNING
+  } else {
+    STATUS_DEAD
+  }
+}
+
+fn signal(args: &Args) -> i32 {
+  let sig = args.signal.unwrap_or(Signal::Term);
+  match Pidfile::signal(&args.pidfile, sig) {
+    Ok(_) => 0,
+    Err(e) => fail(e)
+  }
+}
+
+fn stop(args: &Args) -> i32 {
+  let mut policy = StopPolicy::new();
+  policy.kill(args.kill);
+  if let Some(sig) = args.signal {
+    policy.signal(sig);
+  }
+  if let Some(timeout) = args.timeout {
+    policy.grace(timeout);
+  }
+  match qpidfile::stop(&args.pidfile, &policy) {
+    Ok(StopOutcome::StillRunning) => {
+      eprintln!("qpidfile: process is still running");
+      EXIT_FAILURE
+    }
+    Ok(_) => 0,
+    Err(e) => fail(e)
+  }
+}
+
+fn wait(args: &Args) -> i32 {
+  // Without a timeout, wait in hour-long rounds until the process exits.
+  let timeout = args.timeout.unwrap_or(Duration::from_secs(3600));
+  loop {
+    match qpidfile::wait_for_exit(&args.pidfile, timeout) {
+      Ok(true) => return 0,
+      Ok(false) if args.timeout.is_none() => {}
+      Ok(false) => {
+        eprintln!("qpidfile: process is still running");
+        return EXIT_FAILURE;
+      }
+      Err(e) => return fail(e)
+    }
+  }
+}
+
+fn clean_stale(args: &Args) -> i32 {
+  match qpidfile::clean_stale(&args.pidfile) {
+    Ok(true) => {
+      println!("removed stale pidfile {:?}", args.pidfile);
+      0
+    }
+    Ok(false) => 0,
+    Err(e) => fail(e)
+  }
+}
+
+/// Run the command while holding the pidfile on its behalf.  Exits with the
+/// command's exit status, or `128` plus the signal number if it was
+/// terminated by a signal.
+fn run(args: &Args) -> i32 {
+  let mut cmd = Command::new(&args.argv[0]);
+  cmd.args(&args.argv[1..]);
+  let mut opts = RunOptions::new();
+  opts.write_child_pid(!args.own_pid);
+  match qpidfile::run(&args.pidfile, &mut cmd, &opts) {
+    Ok(status) => match (status.code(), status.signal()) {
+      (Some(code), _) => code,
+      (None, Some(sig)) => 128 + sig,
+      (None, None) => EXIT_FAILURE
+    },
+    Err(e) => {
+      eprintln!("qpidfile: unable to run {:?}; {}", args.argv[0], e);
+      EXIT_FAILURE
+    }
+  }
+}
+
+fn fail(e: Error) -> i32 {
+  eprintln!("qpidfile: {}", e);
+  EXIT_FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+    parse_args(args.iter().map(OsString::from))
+  }
+
+  fn error(args: &[&str]) -> String {
+    match parse(args) {
+      Err(msg) => msg,
+      Ok(_) => panic!("{:?} parsed", args)
+    }
+  }
+
+  #[test]
+  fn parse_command_and_pidfile() {
+    let args = parse(&["status", "my.pid"]).unwrap().unwrap();
+    assert_eq!(args.cmd, "status");
+    assert_eq!(args.pidfile, PathBuf::from("my.pid"));
+    assert_eq!(args.signal, None);
+    assert_eq!(args.timeout, None);
+    assert!(args.kill);
+    assert!(!args.own_pid);
+    assert!(args.argv.is_empty());
+
+    let args = parse(&["--pidfile", "my.pid", "pid"]).unwrap().unwrap();
+    assert_eq!(args.cmd, "pid");
+    assert_eq!(args.pidfile, PathBuf::from("my.pid"));
+  }
+
+  #[test]
+  fn parse_options() {
+    let args = parse(&[
+      "stop", "-p", "my.pid", "-s", "HUP", "-t", "1.5", "--no-kill"
+    ])
+    .unwrap()
+    .unwrap();
+    assert_eq!(args.cmd, "stop");
+    assert_eq!(args.signal, Some(Signal::Hup));
+    assert_eq!(args.timeout, Some(Duration::from_millis(1500)));
+    assert!(!args.kill);
+
+    let args = parse(&["run", "--own-pid", "my.pid", "--", "server", "-v"])
+      .unwrap()
+      .unwrap();
+    assert!(args.own_pid);
+    assert_eq!(args.argv, vec![OsString::from("server"), "-v".into()]);
+  }
+
+  #[test]
+  fn parse_help() {
+    assert!(parse(&["-h"]).unwrap().is_none());
+    assert!(parse(&["status", "--help"]).unwrap().is_none());
+  }
+
+  #[test]
+  fn parse_invalid_timeout() {
+    for t in &["x", "-1", "nan", "inf", "1e300"] {
+      let msg = error(&["wait", "my.pid", "-t", t]);
+      assert_eq!(msg, format!("invalid timeout {:?}", t));
+    }
+  }
+
+  #[test]
+  fn parse_invalid_usage() {
+    assert_eq!(error(&[]), "missing command");
+    assert_eq!(error(&["status"]), "missing pidfile");
+    assert_eq!(
+      error(&["status", "a.pid", "b.pid"]),
+      "unexpected argument \"b.pid\""
+    );
+    assert_eq!(error(&["status", "-x"]), "unknown option \"-x\"");
+    assert_eq!(error(&["status", "-p"]), "-p requires a value");
+    assert_eq!(
+      error(&["signal", "a.pid", "-s", "BOGUS"]),
+      "unknown signal \"BOGUS\""
+    );
+    assert_eq!(error(&["run", "a.pid"]), "missing command to run");
+    assert_eq!(
+      error(&["status", "a.pid", "--", "true"]),
+      "status does not take a command to run"
+    );
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
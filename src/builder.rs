@@ -0,0 +1,274 @@
+//! Configurable creation of pidfiles.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::{Error, Pidfile, StalePolicy};
+
+/// Options and flags which can be used to configure how a [`Pidfile`] is
+/// created.
+///
+/// Besides the pid, the pidfile can optionally carry metadata about the
+/// process, such as its executable, version and listening port.  The pid is
+/// always on the first line, and the metadata follows as `key=value` lines.
+/// Use [`PidfileInfo`](crate::PidfileInfo) to read it back.
+///
+/// ```no_run
+/// use qpidfile::PidfileBuilder;
+///
+/// let pidfile = PidfileBuilder::new()
+///   .mode(0o600)
+///   .create_dirs(true)
+///   .create("/run/myserver/myserver.pid")
+///   .expect("unable to create pidfile");
+/// ```
+#[derive(Clone, Debug)]
+pub struct PidfileBuilder {
+  pub(crate) mode: Option<u32>,
+  pub(crate) uid: Option<u32>,
+  pub(crate) gid: Option<u32>,
+  pub(crate) create_dirs: bool,
+  pub(crate) dir_mode: Option<u32>,
+  pub(crate) dir_uid: Option<u32>,
+  pub(crate) dir_gid: Option<u32>,
+  pub(crate) remove_dirs: bool,
+  pub(crate) exclusive: bool,
+  pub(crate) lock: bool,
+  pub(crate) remove_on_drop: bool,
+  pub(crate) notify: bool,
+  pub(crate) stale_policy: StalePolicy,
+  #[cfg(target_os = "linux")]
+  pub(crate) identity: bool,
+  pub(crate) exe: bool,
+  pub(crate) hostname: bool,
+  pub(crate) version: Option<String>,
+  pub(crate) port: Option<u16>,
+  pub(crate) metadata: BTreeMap<String, String>,
+  #[cfg(feature = "serde")]
+  pub(crate) state: Option<Result<serde_json::Value, String>>
+}
+
+impl Default for PidfileBuilder {
+  fn default() -> Self {
+    PidfileBuilder {
+      mode: None,
+      uid: None,
+      gid: None,
+      create_dirs: false,
+      dir_mode: None,
+      dir_uid: None,
+      dir_gid: None,
+      remove_dirs: false,
+      exclusive: false,
+      lock: true,
+      remove_on_drop: true,
+      notify: false,
+      stale_policy: StalePolicy::Reclaim,
+      #[cfg(target_os = "linux")]
+      identity: false,
+      exe: false,
+      hostname: false,
+      version: None,
+      port: None,
+      metadata: BTreeMap::new(),
+      #[cfg(feature = "serde")]
+      state: None
+    }
+  }
+}
+
+impl PidfileBuilder {
+  /// Create a builder with the same settings as [`Pidfile::new()`] uses.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Set the permission bits of the pidfile.
+  ///
+  /// Unlike the default mode, `0o666`, this is not subject to the process'
+  /// umask.
+  pub fn mode(&mut self, mode: u32) -> &mut Self {
+    self.mode = Some(mode);
+    self
+  }
+
+  /// Set the user owning the pidfile.
+  pub fn owner(&mut self, uid: u32) -> &mut Self {
+    self.uid = Some(uid);
+    self
+  }
+
+  /// Set the group owning the pidfile.
+  pub fn group(&mut self, gid: u32) -> &mut Self {
+    self.gid = Some(gid);
+    self
+  }
+
+  /// Create the pidfile's parent directories if they do not exist.
+  pub fn create_dirs(&mut self, create: bool) -> &mut Self {
+    self.create_dirs = create;
+    self
+  }
+
+  /// Set the permission bits of directories created by
+  /// [`PidfileBuilder::create_dirs()`].
+  ///
+  /// Unlike the default mode, `0o777`, this is not subject to the process'
+  /// umask.
+  pub fn dir_mode(&mut self, mode: u32) -> &mut Self {
+    self.dir_mode = Some(mode);
+    self
+  }
+
+  /// Set the user owning directories created by
+  /// [`PidfileBuilder::create_dirs()`].
+  pub fn dir_owner(&mut self, uid: u32) -> &mut Self {
+    self.dir_uid = Some(uid);
+    self
+  }
+
+  /// Set the group owning directories created by
+  /// [`PidfileBuilder::create_dirs()`].
+  pub fn dir_group(&mut self, gid: u32) -> &mut Self {
+    self.dir_gid = Some(gid);
+    self
+  }
+
+  /// Remove the directories created by [`PidfileBuilder::create_dirs()`]
+  /// when the pidfile is removed, as long as they are empty.
+  pub fn remove_dirs(&mut self, remove: bool) -> &mut Self {
+    self.remove_dirs = remove;
+    self
+  }
+
+  /// Fail with [`Error::Exists`] if the pidfile already exists, even if it
+  /// is stale.
+  pub fn exclusive(&mut self, exclusive: bool) -> &mut Self {
+    self.exclusive = exclusive;
+    self
+  }
+
+  /// Whether to hold an exclusive lock on the pidfile for as long as the
+  /// [`Pidfile`] is alive.
+  pub fn lock(&mut self, lock: bool) -> &mut Self {
+    self.lock = lock;
+    self
+  }
+
+  /// Whether to remove the pidfile when the [`Pidfile`] is dropped.
+  pub fn remove_on_drop(&mut self, remove: bool) -> &mut Self {
+    self.remove_on_drop = remove;
+    self
+  }
+
+  /// Send `MAINPID=` to the service manager (systemd, when running as a
+  /// `Type=notify` service) once the pidfile has been written, and again if
+  /// it is rewritten by [`Pidfile::update_pid()`].
+  ///
+  /// Use [`Pidfile::notify_ready()`] to send `READY=1` once the service is
+  /// up.
+  pub fn notify_mainpid(&mut self, notify: bool) -> &mut Self {
+    self.notify = notify;
+    self
+  }
+
+  /// Set what to do if the pidfile is stale.
+  pub fn stale_policy(&mut self, policy: StalePolicy) -> &mut Self {
+    self.stale_policy = policy;
+    self
+  }
+
+  /// Record the process' start time and the kernel's boot id in the
+  /// pidfile, following the pid.
+  ///
+  /// This allows readers to tell whether the process named in the pidfile
+  /// is the one which wrote it; see [`PidfileInfo::is_alive()`].
+  ///
+  /// [`PidfileInfo::is_alive()`]: crate::PidfileInfo::is_alive
+  #[cfg(target_os = "linux")]
+  pub fn record_identity(&mut self, record: bool) -> &mut Self {
+    self.identity = record;
+    self
+  }
+
+  /// Record the path of the current executable in the pidfile.
+  ///
+  /// When the pidfile names a child process, see [`crate::run()`], the
+  /// child's executable is recorded instead; which is only supported on
+  /// Linux.
+  pub fn record_exe(&mut self, record: bool) -> &mut Self {
+    self.exe = record;
+    self
+  }
+
+  /// Record the name of the host in the pidfile.
+  pub fn record_hostname(&mut self, record: bool) -> &mut Self {
+    self.hostname = record;
+    self
+  }
+
+  /// Record the application's version string in the pidfile.
+  pub fn version<S: Into<String>>(&mut self, version: S) -> &mut Self {
+    self.version = Some(version.into());
+    self
+  }
+
+  /// Record the port the application is listening on in the pidfile.
+  pub fn port(&mut self, port: u16) -> &mut Self {
+    self.port = Some(port);
+    self
+  }
+
+  /// Record application-defined metadata in the pidfile.
+  ///
+  /// Keys may not be empty, contain `=` or whitespace, or be one of the keys
+  /// used by the crate itself; `start_time`, `boot_id`, `exe`, `hostname`,
+  /// `version` and `port`.  Values may not contain line breaks, and leading
+  /// and trailing whitespace is not preserved.  Violations are reported by
+  /// [`PidfileBuilder::create()`] as [`Error::InvalidMetadata`].
+  pub fn metadata<K, V>(&mut self, key: K, value: V) -> &mut Self
+  where
+    K: Into<String>,
+    V: Into<String>
+  {
+    self.metadata.insert(key.into(), value.into());
+    self
+  }
+
+  /// Write the pidfile as a JSON document which, besides the pid and any
+  /// other recorded information, carries the application-defined `state`.
+  ///
+  /// The document is written on a single line, and starts with the pid; for
+  /// instance `{"pid":1234,"state":{"port":8080}}`.  Use
+  /// [`PidfileInfo::read_json()`](crate::PidfileInfo::read_json) to read it
+  /// back.  Note that tools which expect the pid on the first line, such as
+  /// `kill $(cat file)`, can not read it.
+  ///
+  /// If `state` can not be serialized, [`PidfileBuilder::create()`] fails
+  /// with [`Error::InvalidMetadata`].
+  ///
+  /// ```no_run
+  /// use qpidfile::PidfileBuilder;
+  ///
+  /// #[derive(serde::Serialize)]
+  /// struct State {
+  ///   port: u16
+  /// }
+  ///
+  /// let pidfile = PidfileBuilder::new()
+  ///   .json_state(&State { port: 8080 })
+  ///   .create("myserver.pid")
+  ///   .expect("unable to create pidfile");
+  /// ```
+  #[cfg(feature = "serde")]
+  pub fn json_state<T: serde::Serialize>(&mut self, state: &T) -> &mut Self {
+    self.state = Some(serde_json::to_value(state).map_err(|e| e.to_string()));
+    self
+  }
+
+  /// Create the pidfile `fname` using the options in `self`.
+  pub fn create<P: AsRef<Path>>(&self, fname: P) -> Result<Pidfile, Error> {
+    Pidfile::create(fname.as_ref(), self)
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
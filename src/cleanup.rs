@@ -0,0 +1,367 @@
+//! Process-wide registry of pidfiles, and their removal when the process is
+//! terminated without running [`Drop`] implementations.
+//!
+//! Every [`Pidfile`](crate::Pidfile) is recorded in a process-wide registry,
+//! which can be listed using [`pidfiles()`], and whose pidfiles can be
+//! removed in one go using [`remove_all()`].  The registry can hold at most
+//! 64 pidfiles at a time; pidfiles beyond that are not recorded, which can
+//! be checked using
+//! [`Pidfile::is_registered()`](crate::Pidfile::is_registered).
+//!
+//! [`install_handlers()`] installs signal handlers for `SIGTERM`, `SIGINT`
+//! and `SIGHUP`, and an exit handler for [`std::process::exit()`], which
+//! unlink the recorded pidfiles which are configured to be removed on drop.
+//! The signal handlers only use async-signal-safe functions, and re-raise
+//! the signal with its default disposition once the pidfiles have been
+//! removed, so the process terminates as it would have without them.
+//!
+//! ```no_run
+//! use qpidfile::{cleanup, Pidfile};
+//!
+//! cleanup::install_handlers().expect("unable to install handlers");
+//! let pidfile = Pidfile::new("myserver.pid").expect("no pidfile");
+//! // .. run server until terminated by a signal ..
+//! ```
+//!
+//! Similarly, [`install_panic_hook()`] makes sure the recorded pidfiles are
+//! removed when a panic aborts the process.
+//!
+//! Like the [`Drop`] implementation, the handlers only remove pidfiles which
+//! were created (or claimed, see [`Pidfile::update_pid()`]) by the current
+//! process, and which still are the files that were created and still
+//! contain the pid written to them.  The signal handlers check the device
+//! and inode numbers of the pidfile only on Linux, if the C library provides
+//! `statx()`; elsewhere they only check its pid.
+//!
+//! [`Drop`]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+//! [`std::process::exit()`]: https://doc.rust-lang.org/std/process/fn.exit.html
+//! [`Pidfile::update_pid()`]: crate::Pidfile::update_pid
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::panic;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+use crate::{sys, Error, PidfileInfo, Signal};
+
+/// Maximum number of pidfiles which can be recorded at the same time.
+const MAX_PIDFILES: usize = 64;
+
+/// Signals which cause the recorded pidfiles to be removed.
+const SIGNALS: [Signal; 3] = [Signal::Term, Signal::Int, Signal::Hup];
+
+struct Entry {
+  path: CString,
+  /// Whether the pidfile is configured to be removed on drop.
+  auto_remove: bool,
+  /// The process which owns the pidfile.
+  owner: AtomicU32,
+  /// The pid written to the pidfile.
+  pid: AtomicU32,
+  dev: AtomicU64,
+  ino: AtomicU64,
+  /// Set once the pidfile has been detached from the registry.
+  detached: AtomicBool,
+  /// Set once the pidfile has been removed by remove_all().
+  removed: AtomicBool
+}
+
+impl Entry {
+  /// Whether the entry is managed by the registry on behalf of the process
+  /// `pid`.
+  fn is_live(&self, pid: u32) -> bool {
+    self.owner.load(Ordering::SeqCst) == pid
+      && !self.detached.load(Ordering::SeqCst)
+      && !self.removed.load(Ordering::SeqCst)
+  }
+
+  fn path(&self) -> &Path {
+    Path::new(OsStr::from_bytes(self.path.as_bytes()))
+  }
+}
+
+/// The table of recorded pidfiles.  Entries are allocated before they are
+/// published, so the signal handlers never need to allocate.
+static SLOTS: [AtomicPtr<Entry>; MAX_PIDFILES] =
+  [const { AtomicPtr::new(ptr::null_mut()) }; MAX_PIDFILES];
+
+/// Number of threads currently walking the table.  Entries are not freed
+/// while this is non-zero.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+static INSTALL: Once = Once::new();
+
+static INSTALL_PANIC_HOOK: Once = Once::new();
+
+/// Install signal handlers for `SIGTERM`, `SIGINT` and `SIGHUP`, and an exit
+/// handler, which remove all recorded pidfiles.
+///
+/// Any handlers previously installed for these signals are replaced; signals
+/// which are ignored (for instance `SIGHUP` under `nohup`) remain ignored.
+/// Calling this function more than once has no effect.
+pub fn install_handlers() -> Result<(), Error> {
+  let mut res = Ok(());
+  INSTALL.call_once(|| res = install());
+  res
+}
+
+fn install() -> Result<(), Error> {
+  let handler = on_signal as extern "C" fn(c_int) as usize;
+  for sig in &SIGNALS {
+    let prev = sys::set_signal_handler(sig.as_raw(), handler)?;
+    if prev == sys::SIG_IGN {
+      sys::set_signal_handler(sig.as_raw(), sys::SIG_IGN)?;
+    }
+  }
+  sys::at_exit(on_exit)?;
+  Ok(())
+}
+
+/// Install a panic hook which removes all recorded pidfiles, before calling
+/// the previously installed panic hook.  Like [`remove_all()`], pidfiles
+/// which have been replaced or rewritten by someone else are left alone.
+///
+/// The pidfiles are only removed if the crate is built with
+/// `panic = "abort"`; when panics unwind, the [`Drop`] implementation removes
+/// the pidfiles if the panic terminates the process, and a panic in a thread
+/// other than the main thread does not terminate the process.  Calling this
+/// function more than once has no effect.
+///
+/// [`Drop`]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+pub fn install_panic_hook() {
+  INSTALL_PANIC_HOOK.call_once(|| {
+    let prev = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+      if cfg!(panic = "abort") {
+        remove_owned();
+      }
+      prev(info);
+    }));
+  });
+}
+
+extern "C" fn on_signal(sig: c_int) {
+  unlink_all();
+  // The signal is blocked while its handler runs, so it is delivered, with
+  // its default disposition, once the handler returns.
+  let _ = sys::set_signal_handler(sig, sys::SIG_DFL);
+  sys::raise_signal(sig);
+}
+
+extern "C" fn on_exit() {
+  remove_owned();
+}
+
+/// Get the paths of all pidfiles in the registry which belong to the current
+/// process.
+pub fn pidfiles() -> Vec<PathBuf> {
+  let pid = sys::current_pid();
+  let mut paths = Vec::new();
+  for_each_entry(|entry| {
+    if entry.is_live(pid) {
+      paths.push(entry.path().to_path_buf());
+    }
+  });
+  paths
+}
+
+/// Remove all pidfiles in the registry which belong to the current process.
+///
+/// Like the [`Drop`] implementation, pidfiles which have been replaced or
+/// rewritten by someone else are left alone.  All pidfiles are processed
+/// even if some of them can't be removed; the first error is returned.
+///
+/// The [`Pidfile`](crate::Pidfile) objects remain valid, but will not
+/// attempt to remove their pidfiles again.
+///
+/// [`Drop`]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+pub fn remove_all() -> Result<(), Error> {
+  let pid = sys::current_pid();
+  let mut res = Ok(());
+  for_each_entry(|entry| {
+    if !entry.is_live(pid) {
+      return;
+    }
+    if let Err(e) = remove_entry(entry) {
+      if res.is_ok() {
+        res = Err(e);
+      }
+    }
+  });
+  res
+}
+
+fn remove_entry(entry: &Entry) -> Result<(), Error> {
+  use std::os::unix::fs::MetadataExt;
+
+  let path = entry.path();
+  let md = std::fs::metadata(path)?;
+  if md.dev() != entry.dev.load(Ordering::SeqCst)
+    || md.ino() != entry.ino.load(Ordering::SeqCst)
+    || PidfileInfo::read(path)?.pid() != entry.pid.load(Ordering::SeqCst)
+  {
+    return Err(Error::NotOwner(path.to_path_buf()));
+  }
+  std::fs::remove_file(path)?;
+  entry.removed.store(true, Ordering::SeqCst);
+  Ok(())
+}
+
+/// Detach the pidfile `fname` from the registry.
+///
+/// A detached pidfile is no longer listed by [`pidfiles()`], and is not
+/// removed by [`remove_all()`] or the cleanup handlers.  It is still removed
+/// when its [`Pidfile`](crate::Pidfile) is dropped, if it is configured to
+/// be.  Returns `false` if `fname` is not in the registry.
+pub fn detach<P: AsRef<Path>>(fname: P) -> bool {
+  let fname = fname.as_ref();
+  let pid = sys::current_pid();
+  let mut found = false;
+  for_each_entry(|entry| {
+    if entry.is_live(pid) && entry.path() == fname {
+      entry.detached.store(true, Ordering::SeqCst);
+      found = true;
+    }
+  });
+  found
+}
+
+/// Unlink all pidfiles recorded by the current process which are configured
+/// to be removed on drop, and which have not been replaced or rewritten.
+/// Async-signal-safe.
+fn unlink_all() {
+  let pid = sys::current_pid();
+  for_each_entry(|entry| {
+    if entry.auto_remove && entry.is_live(pid) && is_unchanged(entry) {
+      sys::unlink_quietly(&entry.path);
+    }
+  });
+}
+
+/// Check that the pidfile recorded in `entry` still is the same file, and
+/// that it still contains the same pid.  Async-signal-safe.
+fn is_unchanged(entry: &Entry) -> bool {
+  #[cfg(target_os = "linux")]
+  {
+    match sys::file_id(&entry.path) {
+      Ok(Some((dev, ino)))
+        if dev == entry.dev.load(Ordering::SeqCst)
+          && ino == entry.ino.load(Ordering::SeqCst) => {}
+      // Without statx() only the pid can be checked.
+      Ok(None) => {}
+      _ => return false
+    }
+  }
+  sys::read_pid_quietly(&entry.path) == Some(entry.pid.load(Ordering::SeqCst))
+}
+
+/// Remove all pidfiles recorded by the current process which are configured
+/// to be removed on drop, ignoring errors.  Unlike [`unlink_all()`] this is
+/// not async-signal-safe.
+fn remove_owned() {
+  let pid = sys::current_pid();
+  for_each_entry(|entry| {
+    if entry.auto_remove && entry.is_live(pid) {
+      let _ = remove_entry(entry);
+    }
+  });
+}
+
+/// Call `f` for every entry in the table.  Async-signal-safe, as long as `f`
+/// is.
+fn for_each_entry<F: FnMut(&Entry)>(mut f: F) {
+  ACTIVE.fetch_add(1, Ordering::SeqCst);
+  for slot in &SLOTS {
+    let entry = slot.load(Ordering::SeqCst);
+    if !entry.is_null() {
+      // SAFETY: Entries are not freed while ACTIVE is non-zero.
+      f(unsafe { &*entry });
+    }
+  }
+  ACTIVE.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Record the pidfile `fname`, containing `pid` and with the device and inode
+/// numbers `dev` and `ino`, owned by the process `owner`.
+///
+/// Returns the slot the pidfile was recorded in, or `None` if the table is
+/// full.
+pub(crate) fn register(
+  fname: &Path,
+  owner: u32,
+  pid: u32,
+  dev: u64,
+  ino: u64,
+  auto_remove: bool
+) -> Option<usize> {
+  let path = CString::new(fname.as_os_str().as_bytes()).ok()?;
+  #[cfg(target_os = "linux")]
+  sys::resolve_statx();
+  let entry = Box::into_raw(Box::new(Entry {
+    path,
+    auto_remove,
+    owner: AtomicU32::new(owner),
+    pid: AtomicU32::new(pid),
+    dev: AtomicU64::new(dev),
+    ino: AtomicU64::new(ino),
+    detached: AtomicBool::new(false),
+    removed: AtomicBool::new(false)
+  }));
+  for (idx, slot) in SLOTS.iter().enumerate() {
+    let res = slot.compare_exchange(
+      ptr::null_mut(),
+      entry,
+      Ordering::SeqCst,
+      Ordering::SeqCst
+    );
+    if res.is_ok() {
+      return Some(idx);
+    }
+  }
+  // SAFETY: The entry was never published.
+  drop(unsafe { Box::from_raw(entry) });
+  None
+}
+
+/// Update the owner, pid, device and inode numbers of the pidfile recorded in
+/// `slot`, after it has been rewritten.
+pub(crate) fn update(slot: usize, owner: u32, pid: u32, dev: u64, ino: u64) {
+  let entry = SLOTS[slot].load(Ordering::SeqCst);
+  if !entry.is_null() {
+    // SAFETY: Only the owner of the slot frees its entry.
+    let entry = unsafe { &*entry };
+    entry.dev.store(dev, Ordering::SeqCst);
+    entry.ino.store(ino, Ordering::SeqCst);
+    entry.pid.store(pid, Ordering::SeqCst);
+    entry.owner.store(owner, Ordering::SeqCst);
+  }
+}
+
+/// Check whether the pidfile recorded in `slot` has been removed by
+/// [`remove_all()`].
+pub(crate) fn is_removed(slot: usize) -> bool {
+  let entry = SLOTS[slot].load(Ordering::SeqCst);
+  // SAFETY: Only the owner of the slot frees its entry.
+  !entry.is_null() && unsafe { &*entry }.removed.load(Ordering::SeqCst)
+}
+
+/// Forget the pidfile recorded in `slot`.
+pub(crate) fn unregister(slot: usize) {
+  let entry = SLOTS[slot].swap(ptr::null_mut(), Ordering::SeqCst);
+  if entry.is_null() {
+    return;
+  }
+  // A handler may still be using the entry.
+  while ACTIVE.load(Ordering::SeqCst) != 0 {
+    std::thread::yield_now();
+  }
+  // SAFETY: The entry is no longer published, and no handler is using it.
+  drop(unsafe { Box::from_raw(entry) });
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
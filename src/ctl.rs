@@ -0,0 +1,216 @@
+//! Controlling the process named in a pidfile.
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{sys, Error, PidfileInfo, Signal};
+
+/// How often to check whether a process has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Wait for the process named in the pidfile `fname` to exit.
+///
+/// Returns `Ok(true)` if the process exited, or the pidfile was removed (or
+/// rewritten by another process), within `timeout`.  Returns `Ok(false)` if
+/// the process is still running once `timeout` has elapsed.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// if !qpidfile::wait_for_exit("myserver.pid", Duration::from_secs(10))
+///   .expect("unable to read pidfile")
+/// {
+///   eprintln!("server did not terminate in time");
+/// }
+/// ```
+pub fn wait_for_exit<P: AsRef<Path>>(
+  fname: P,
+  timeout: Duration
+) -> Result<bool, Error> {
+  let fname = fname.as_ref();
+  let deadline = Instant::now().checked_add(timeout);
+  let mut pid = None;
+  loop {
+    match PidfileInfo::read(fname) {
+      Ok(info) => {
+        if *pid.get_or_insert(info.pid()) != info.pid() {
+          return Ok(true);
+        }
+        if !info.is_alive()? {
+          return Ok(true);
+        }
+      }
+      Err(Error::Missing(_)) => return Ok(true),
+      Err(e) => return Err(e)
+    }
+
+    if !poll_sleep(deadline) {
+      return Ok(false);
+    }
+  }
+}
+
+/// Remove the pidfile `fname` if it is stale; i.e. if the process named in
+/// it no longer exists.
+///
+/// Returns `Ok(true)` if the pidfile was removed, and `Ok(false)` if the
+/// process is alive or there is no pidfile.
+pub fn clean_stale<P: AsRef<Path>>(fname: P) -> Result<bool, Error> {
+  let fname = fname.as_ref();
+  let info = match PidfileInfo::read(fname) {
+    Ok(info) => info,
+    Err(Error::Missing(_)) => return Ok(false),
+    Err(e) => return Err(e)
+  };
+  if info.is_alive()? {
+    return Ok(false);
+  }
+  crate::remove_stale(fname, info.pid())
+}
+
+/// How to stop the process named in a pidfile.
+#[derive(Clone, Debug)]
+pub struct StopPolicy {
+  signal: Signal,
+  grace: Duration,
+  kill: bool,
+  kill_timeout: Duration
+}
+
+impl Default for StopPolicy {
+  fn default() -> Self {
+    StopPolicy {
+      signal: Signal::Term,
+      grace: Duration::from_secs(10),
+      kill: true,
+      kill_timeout: Duration::from_secs(5)
+    }
+  }
+}
+
+impl StopPolicy {
+  /// Create a policy which sends `SIGTERM`, waits up to 10 seconds, and then
+  /// sends `SIGKILL` and waits up to another 5 seconds.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The signal used to ask the process to terminate.
+  pub fn signal(&mut self, sig: Signal) -> &mut Self {
+    self.signal = sig;
+    self
+  }
+
+  /// How long to wait for the process to terminate before escalating.
+  pub fn grace(&mut self, grace: Duration) -> &mut Self {
+    self.grace = grace;
+    self
+  }
+
+  /// Whether to send `SIGKILL` if the process has not terminated within the
+  /// grace period.
+  pub fn kill(&mut self, kill: bool) -> &mut Self {
+    self.kill = kill;
+    self
+  }
+
+  /// How long to wait for the process to terminate after `SIGKILL` has been
+  /// sent.
+  pub fn kill_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.kill_timeout = timeout;
+    self
+  }
+}
+
+/// The result of [`stop()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopOutcome {
+  /// There was no pidfile, or the process named in it was not running.
+  NotRunning,
+
+  /// The process terminated within the grace period.
+  Terminated,
+
+  /// The process was killed.
+  Killed,
+
+  /// The process is still running.
+  StillRunning
+}
+
+/// Stop the process named in the pidfile `fname`.
+///
+/// The process is sent the policy's signal, and if it has not terminated
+/// within the grace period it is sent `SIGKILL`.  Once the process has
+/// terminated, the pidfile is removed if the process left it behind.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use qpidfile::StopPolicy;
+///
+/// let mut policy = StopPolicy::new();
+/// policy.grace(Duration::from_secs(30));
+/// qpidfile::stop("myserver.pid", &policy).expect("unable to stop server");
+/// ```
+pub fn stop<P: AsRef<Path>>(
+  fname: P,
+  policy: &StopPolicy
+) -> Result<StopOutcome, Error> {
+  let fname = fname.as_ref();
+  let info = match PidfileInfo::read(fname) {
+    Ok(info) => info,
+    Err(Error::Missing(_)) => return Ok(StopOutcome::NotRunning),
+    Err(e) => return Err(e)
+  };
+  let pid = info.pid();
+
+  let outcome = match info.signal(policy.signal) {
+    Ok(()) if wait_pid(pid, policy.grace)? => StopOutcome::Terminated,
+    Ok(()) if !policy.kill => return Ok(StopOutcome::StillRunning),
+    Ok(()) => match info.signal(Signal::Kill) {
+      Ok(()) if wait_pid(pid, policy.kill_timeout)? => StopOutcome::Killed,
+      Ok(()) => return Ok(StopOutcome::StillRunning),
+      Err(Error::Stale { .. }) => StopOutcome::Terminated,
+      Err(e) => return Err(e)
+    },
+    Err(Error::Stale { .. }) => StopOutcome::NotRunning,
+    Err(e) => return Err(e)
+  };
+
+  crate::remove_stale(fname, pid)?;
+  Ok(outcome)
+}
+
+/// Wait for the process `pid` to terminate.  Returns `Ok(false)` if it is
+/// still alive after `timeout`.
+fn wait_pid(pid: u32, timeout: Duration) -> Result<bool, Error> {
+  let deadline = Instant::now().checked_add(timeout);
+  loop {
+    if !sys::is_alive(pid)? {
+      return Ok(true);
+    }
+    if !poll_sleep(deadline) {
+      return Ok(false);
+    }
+  }
+}
+
+/// Sleep for one poll interval, but not past `deadline`, which is `None` if
+/// there is none.  Returns `false`, without sleeping, if the deadline has
+/// passed.
+fn poll_sleep(deadline: Option<Instant>) -> bool {
+  let interval = match deadline {
+    Some(deadline) => {
+      let now = Instant::now();
+      if now >= deadline {
+        return false;
+      }
+      POLL_INTERVAL.min(deadline - now)
+    }
+    None => POLL_INTERVAL
+  };
+  thread::sleep(interval);
+  true
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,185 @@
+//! Turn the current process into a daemon which owns a pidfile.
+//!
+//! ```no_run
+//! use qpidfile::daemonize::Daemonize;
+//!
+//! // Only the daemon process returns from start(); the launching process
+//! // exits with a zero status once the pidfile has been written, or a
+//! // non-zero status if the daemon failed to start.
+//! let pidfile = Daemonize::new("/run/myserver.pid")
+//!   .stderr("/var/log/myserver.err")
+//!   .start()
+//!   .expect("unable to daemonize");
+//! // .. run server ..
+//! ```
+//!
+//! Forking is only safe while the process is single-threaded, so
+//! [`Daemonize::start()`] should be called as early as possible; before any
+//! threads have been spawned.
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::{sys, Error, Pidfile, PidfileBuilder};
+
+/// Prefix of the message sent to the launching process on success.
+const STATUS_OK: &[u8] = b"OK";
+
+/// Configuration of the daemonization process.
+#[derive(Clone, Debug)]
+pub struct Daemonize {
+  pidfile: PathBuf,
+  opts: PidfileBuilder,
+  workdir: PathBuf,
+  umask: u32,
+  stdout: Option<PathBuf>,
+  stderr: Option<PathBuf>
+}
+
+impl Daemonize {
+  /// Create a daemonization configuration which will write the daemon's pid
+  /// to `pidfile`.
+  ///
+  /// By default the daemon changes its working directory to `/`, sets its
+  /// umask to `0o027`, and redirects its standard streams to `/dev/null`.
+  pub fn new<P: AsRef<Path>>(pidfile: P) -> Self {
+    Daemonize {
+      pidfile: pidfile.as_ref().to_path_buf(),
+      opts: PidfileBuilder::new(),
+      workdir: PathBuf::from("/"),
+      umask: 0o027,
+      stdout: None,
+      stderr: None
+    }
+  }
+
+  /// Options used to create the pidfile.
+  pub fn pidfile_options(&mut self, opts: PidfileBuilder) -> &mut Self {
+    self.opts = opts;
+    self
+  }
+
+  /// Directory the daemon should change its working directory to.
+  pub fn working_directory<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+    self.workdir = dir.as_ref().to_path_buf();
+    self
+  }
+
+  /// The daemon's file mode creation mask.
+  pub fn umask(&mut self, mask: u32) -> &mut Self {
+    self.umask = mask;
+    self
+  }
+
+  /// Append the daemon's standard output to the file `fname`, rather than
+  /// discarding it.
+  pub fn stdout<P: AsRef<Path>>(&mut self, fname: P) -> &mut Self {
+    self.stdout = Some(fname.as_ref().to_path_buf());
+    self
+  }
+
+  /// Append the daemon's standard error to the file `fname`, rather than
+  /// discarding it.
+  pub fn stderr<P: AsRef<Path>>(&mut self, fname: P) -> &mut Self {
+    self.stderr = Some(fname.as_ref().to_path_buf());
+    self
+  }
+
+  /// Detach from the controlling terminal using the double-fork technique
+  /// and create the pidfile from the final process.
+  ///
+  /// The calling process does not return from this function; it waits for
+  /// the daemon to report whether it started successfully and exits with
+  /// status `0` if it did.  If it did not, the error is written to the
+  /// calling process' stderr and it exits with status `1`.
+  ///
+  /// If the daemon fails to start, after having forked, the error is
+  /// returned in the daemon process as well; the caller should terminate it.
+  pub fn start(&self) -> Result<Pidfile, Error> {
+    let (mut rx, tx) = sys::create_pipe()?;
+
+    match unsafe { sys::fork_process() }? {
+      0 => {}
+      pid => {
+        drop(tx);
+        launcher(&mut rx, pid)
+      }
+    }
+    drop(rx);
+
+    // Any failure from this point on must be reported to the launcher.
+    let mut tx = tx;
+    let res = self.detach().and_then(|_| self.setup());
+    let msg = match res {
+      Ok(_) => STATUS_OK.to_vec(),
+      Err(ref e) => e.to_string().into_bytes()
+    };
+    let _ = tx.write_all(&msg);
+    res
+  }
+
+  /// Create a new session and fork again, so the daemon is not a session
+  /// leader and can not acquire a controlling terminal.
+  fn detach(&self) -> Result<(), Error> {
+    sys::new_session()?;
+    match unsafe { sys::fork_process() }? {
+      0 => Ok(()),
+      _ => sys::exit_immediately(0)
+    }
+  }
+
+  fn setup(&self) -> Result<Pidfile, Error> {
+    sys::set_umask(self.umask);
+
+    // Open files, and resolve the pidfile's path, relative to the original
+    // working directory.
+    let devnull = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    let stdout = self.stdout.as_ref().map(|p| open_log(p)).transpose()?;
+    let stderr = self.stderr.as_ref().map(|p| open_log(p)).transpose()?;
+    let pidfile = if self.pidfile.is_absolute() {
+      self.pidfile.clone()
+    } else {
+      std::env::current_dir()?.join(&self.pidfile)
+    };
+
+    std::env::set_current_dir(&self.workdir)?;
+
+    sys::dup_onto(devnull.as_raw_fd(), 0)?;
+    let fd = stdout.as_ref().unwrap_or(&devnull).as_raw_fd();
+    sys::dup_onto(fd, 1)?;
+    let fd = stderr.as_ref().unwrap_or(&devnull).as_raw_fd();
+    sys::dup_onto(fd, 2)?;
+
+    self.opts.create(pidfile)
+  }
+}
+
+fn open_log(fname: &Path) -> Result<File, Error> {
+  Ok(OpenOptions::new().create(true).append(true).open(fname)?)
+}
+
+/// Wait for the daemon to report its status, and terminate the launching
+/// process accordingly.
+fn launcher(rx: &mut File, child: i32) -> ! {
+  let mut msg = Vec::new();
+  let res = rx.read_to_end(&mut msg);
+
+  // Reap the intermediate process.
+  let _ = sys::wait_child(child);
+
+  match res {
+    Ok(_) if msg == STATUS_OK => process::exit(0),
+    Ok(_) if msg.is_empty() => {
+      eprintln!("Daemon terminated before reporting its status");
+    }
+    Ok(_) => {
+      eprintln!("Daemon failed to start; {}", String::from_utf8_lossy(&msg));
+    }
+    Err(e) => eprintln!("Unable to read daemon status; {}", e)
+  }
+  process::exit(1)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
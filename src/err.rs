@@ -0,0 +1,79 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::ParseError;
+
+/// Errors returned by the pidfile operations.
+#[derive(Debug)]
+pub enum Error {
+  /// Another process, identified by `pid`, owns the pidfile.
+  AlreadyRunning { pid: u32 },
+
+  /// The pidfile exists, and was requested to be created exclusively.
+  Exists(PathBuf),
+
+  /// The pidfile names a process, identified by `pid`, which no longer
+  /// exists.
+  Stale { pid: u32 },
+
+  /// Permission to signal, or inspect, the process identified by `pid` was
+  /// denied.
+  PermissionDenied { pid: u32 },
+
+  /// The pidfile no longer belongs to this process; it has been replaced or
+  /// rewritten by someone else.
+  NotOwner(PathBuf),
+
+  /// There's no pidfile at the given path.
+  Missing(PathBuf),
+
+  /// Metadata to be written to the pidfile is not valid.
+  InvalidMetadata(String),
+
+  /// The pidfile's contents could not be parsed.
+  Parse(ParseError),
+
+  /// An I/O operation failed.
+  Io(io::Error)
+}
+
+/// The underlying errors of [`Error::Parse`] and [`Error::Io`] are part of
+/// their messages, so they are not also reported as sources.
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::AlreadyRunning { pid } => {
+        write!(f, "already running (pid {})", pid)
+      }
+      Error::Exists(p) => write!(f, "pidfile {:?} already exists", p),
+      Error::Stale { pid } => write!(f, "stale pidfile (pid {})", pid),
+      Error::PermissionDenied { pid } => {
+        write!(f, "permission denied accessing pid {}", pid)
+      }
+      Error::NotOwner(p) => {
+        write!(f, "pidfile {:?} no longer belongs to this process", p)
+      }
+      Error::Missing(p) => write!(f, "pidfile {:?} does not exist", p),
+      Error::InvalidMetadata(s) => write!(f, "invalid metadata; {}", s),
+      Error::Parse(e) => write!(f, "unable to parse pidfile; {}", e),
+      Error::Io(e) => write!(f, "I/O error; {}", e)
+    }
+  }
+}
+
+impl From<ParseError> for Error {
+  fn from(err: ParseError) -> Self {
+    Error::Parse(err)
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(err: io::Error) -> Self {
+    Error::Io(err)
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
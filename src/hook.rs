@@ -0,0 +1,48 @@
+//! Reporting of errors which can't be returned to the caller.
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::Error;
+
+type Hook = Box<dyn Fn(&Path, &Error) + Send + Sync>;
+
+static DROP_ERROR_HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Register a function to be called when a [`Pidfile`](crate::Pidfile) can
+/// not be removed when it is dropped.
+///
+/// The hook is called with the pidfile's path and the error.  This replaces
+/// the default behavior of printing the error to stderr, as well as any
+/// previously registered hook.  The hook must not register or clear hooks
+/// itself.
+///
+/// ```
+/// qpidfile::set_drop_error_hook(|fname, err| {
+///   // Route to the application's logger instead of stderr.
+///   let _ = (fname, err);
+/// });
+/// ```
+pub fn set_drop_error_hook<F>(hook: F)
+where
+  F: Fn(&Path, &Error) + Send + Sync + 'static
+{
+  let mut g = DROP_ERROR_HOOK.write().unwrap_or_else(|e| e.into_inner());
+  *g = Some(Box::new(hook));
+}
+
+/// Unregister the drop error hook, restoring the default behavior of printing
+/// errors to stderr.
+pub fn clear_drop_error_hook() {
+  let mut g = DROP_ERROR_HOOK.write().unwrap_or_else(|e| e.into_inner());
+  *g = None;
+}
+
+pub(crate) fn report(fname: &Path, err: &Error) {
+  let g = DROP_ERROR_HOOK.read().unwrap_or_else(|e| e.into_inner());
+  match &*g {
+    Some(hook) => hook(fname, err),
+    None => eprintln!("Unable to remove pidfile {:?}; {}", fname, err)
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
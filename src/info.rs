@@ -0,0 +1,627 @@
+//! Read-side of pidfiles.
+//!
+//! A pidfile consists of the pid, in ascii base-10, on the first line.  It
+//! may be followed by lines of `key=value` pairs describing the process, so
+//! the pid can still be read using, for instance, `head -n 1`.
+//!
+//! With the `serde` feature, a pidfile may instead be a JSON document, which
+//! carries the same information as well as application-defined state.
+//! Without the feature only the pid, which such documents start with, is
+//! read from them.
+use std::fmt;
+use std::io;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::{sys, Error, Signal};
+
+/// Reasons the contents of a pidfile could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+  /// The pidfile is empty, or only contains whitespace.
+  Empty,
+
+  /// The pidfile does not contain an ascii base-10 number.
+  Malformed(String),
+
+  /// The pidfile contains a number which is not a valid process identifier.
+  OutOfRange(String)
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ParseError::Empty => write!(f, "pidfile is empty"),
+      ParseError::Malformed(s) => write!(f, "malformed pid {:?}", s),
+      ParseError::OutOfRange(s) => write!(f, "pid {} is out of range", s)
+    }
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Key of the process' start time, in clock ticks since boot.
+pub(crate) const KEY_START_TIME: &str = "start_time";
+
+/// Key of the kernel's boot id at the time the pidfile was written.
+pub(crate) const KEY_BOOT_ID: &str = "boot_id";
+
+pub(crate) const KEY_EXE: &str = "exe";
+pub(crate) const KEY_HOSTNAME: &str = "hostname";
+pub(crate) const KEY_VERSION: &str = "version";
+pub(crate) const KEY_PORT: &str = "port";
+
+/// Keys which can not be used for application-defined metadata.
+pub(crate) const RESERVED_KEYS: &[&str] = &[
+  KEY_START_TIME,
+  KEY_BOOT_ID,
+  KEY_EXE,
+  KEY_HOSTNAME,
+  KEY_VERSION,
+  KEY_PORT
+];
+
+/// Information parsed from an existing pidfile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PidfileInfo {
+  pub(crate) pid: u32,
+  pub(crate) start_time: Option<u64>,
+  pub(crate) boot_id: Option<String>,
+  pub(crate) exe: Option<PathBuf>,
+  pub(crate) hostname: Option<String>,
+  pub(crate) version: Option<String>,
+  pub(crate) port: Option<u16>,
+  pub(crate) metadata: BTreeMap<String, String>,
+  #[cfg(feature = "serde")]
+  pub(crate) state: Option<serde_json::Value>
+}
+
+impl PidfileInfo {
+  /// Information about the process `pid`, without any metadata.
+  pub(crate) fn new(pid: u32) -> Self {
+    PidfileInfo {
+      pid,
+      start_time: None,
+      boot_id: None,
+      exe: None,
+      hostname: None,
+      version: None,
+      port: None,
+      metadata: BTreeMap::new(),
+      #[cfg(feature = "serde")]
+      state: None
+    }
+  }
+
+  /// Read and parse the pidfile at `fname`.
+  pub fn read<P: AsRef<Path>>(fname: P) -> Result<Self, Error> {
+    let fname = fname.as_ref();
+    let buf = match std::fs::read(fname) {
+      Ok(buf) => buf,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        return Err(Error::Missing(fname.to_path_buf()));
+      }
+      Err(e) => return Err(Error::Io(e))
+    };
+    let s = String::from_utf8_lossy(&buf);
+    Ok(s.parse()?)
+  }
+
+  /// The process identifier stored in the pidfile.
+  pub fn pid(&self) -> u32 {
+    self.pid
+  }
+
+  /// The start time of the process, in clock ticks since boot, if it was
+  /// recorded in the pidfile.
+  pub fn start_time(&self) -> Option<u64> {
+    self.start_time
+  }
+
+  /// The kernel's boot id when the pidfile was written, if it was recorded
+  /// in the pidfile.
+  pub fn boot_id(&self) -> Option<&str> {
+    self.boot_id.as_deref()
+  }
+
+  /// The path of the executable which wrote the pidfile, if it was recorded
+  /// in the pidfile.
+  pub fn recorded_exe(&self) -> Option<&Path> {
+    self.exe.as_deref()
+  }
+
+  /// The name of the host the pidfile was written on, if it was recorded in
+  /// the pidfile.
+  pub fn hostname(&self) -> Option<&str> {
+    self.hostname.as_deref()
+  }
+
+  /// The version string of the application which wrote the pidfile, if it
+  /// was recorded in the pidfile.
+  pub fn version(&self) -> Option<&str> {
+    self.version.as_deref()
+  }
+
+  /// The port the process is listening on, if it was recorded in the
+  /// pidfile.
+  pub fn port(&self) -> Option<u16> {
+    self.port
+  }
+
+  /// Get the value of the application-defined metadata `key`.
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.metadata.get(key).map(String::as_str)
+  }
+
+  /// All the application-defined metadata in the pidfile.
+  pub fn metadata(&self) -> &BTreeMap<String, String> {
+    &self.metadata
+  }
+
+  /// Read the JSON pidfile at `fname`, and deserialize the application-defined
+  /// state in it.
+  ///
+  /// ```no_run
+  /// use qpidfile::PidfileInfo;
+  ///
+  /// #[derive(serde::Deserialize)]
+  /// struct State {
+  ///   port: u16
+  /// }
+  ///
+  /// let (info, state) = PidfileInfo::read_json::<State, _>("myserver.pid")
+  ///   .expect("unable to read pidfile");
+  /// println!("pid {} is listening on port {}", info.pid(), state.port);
+  /// ```
+  ///
+  /// See [`PidfileBuilder::json_state()`].
+  ///
+  /// [`PidfileBuilder::json_state()`]: crate::PidfileBuilder::json_state
+  #[cfg(feature = "serde")]
+  pub fn read_json<T, P>(fname: P) -> Result<(Self, T), Error>
+  where
+    T: serde::de::DeserializeOwned,
+    P: AsRef<Path>
+  {
+    let info = Self::read(fname)?;
+    let state = info
+      .state()?
+      .ok_or_else(|| ParseError::Malformed("no state".to_string()))?;
+    Ok((info, state))
+  }
+
+  /// Deserialize the application-defined state in a JSON pidfile.  Returns
+  /// `Ok(None)` if the pidfile does not carry any state.
+  #[cfg(feature = "serde")]
+  pub fn state<T>(&self) -> Result<Option<T>, Error>
+  where
+    T: serde::de::DeserializeOwned
+  {
+    match self.state {
+      Some(ref state) => match T::deserialize(state) {
+        Ok(state) => Ok(Some(state)),
+        Err(e) => Err(ParseError::Malformed(e.to_string()).into())
+      },
+      None => Ok(None)
+    }
+  }
+
+  /// Check whether the process named in the pidfile exists.
+  ///
+  /// If the pidfile contains the process' start time and boot id (see
+  /// [`PidfileBuilder::record_identity()`]) the process is only considered
+  /// alive if they match the running process, so an unrelated process which
+  /// reused the pid is not mistaken for the pidfile's owner.
+  ///
+  /// [`PidfileBuilder::record_identity()`]:
+  /// crate::PidfileBuilder::record_identity
+  pub fn is_alive(&self) -> Result<bool, Error> {
+    if !sys::is_alive(self.pid)? {
+      return Ok(false);
+    }
+    #[cfg(target_os = "linux")]
+    {
+      if let Some(ref boot_id) = self.boot_id {
+        if *boot_id != read_boot_id()? {
+          return Ok(false);
+        }
+      }
+      if let Some(start_time) = self.start_time {
+        if Some(start_time) != read_start_time(self.pid)? {
+          return Ok(false);
+        }
+      }
+    }
+    Ok(true)
+  }
+
+  /// Send the signal `sig` to the process named in the pidfile.
+  ///
+  /// Returns [`Error::Stale`] if the process does not exist, or if its
+  /// identity does not match the one recorded in the pidfile, and
+  /// [`Error::PermissionDenied`] if the caller is not allowed to signal it.
+  pub fn signal(&self, sig: Signal) -> Result<(), Error> {
+    if (self.start_time.is_some() || self.boot_id.is_some())
+      && !self.is_alive()?
+    {
+      return Err(Error::Stale { pid: self.pid });
+    }
+    sys::send_signal(self.pid, sig.as_raw()).map_err(|e| {
+      if e.raw_os_error() == Some(sys::ESRCH) {
+        Error::Stale { pid: self.pid }
+      } else if e.kind() == io::ErrorKind::PermissionDenied {
+        Error::PermissionDenied { pid: self.pid }
+      } else {
+        Error::Io(e)
+      }
+    })
+  }
+
+  /// Get the path of the executable the process named in the pidfile is
+  /// running.
+  #[cfg(target_os = "linux")]
+  pub fn exe(&self) -> Result<PathBuf, Error> {
+    let link = format!("/proc/{}/exe", self.pid);
+    match std::fs::read_link(link) {
+      Ok(exe) => Ok(exe),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        Err(Error::Stale { pid: self.pid })
+      }
+      Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+        Err(Error::PermissionDenied { pid: self.pid })
+      }
+      Err(e) => Err(Error::Io(e))
+    }
+  }
+
+  /// Make sure the process named in the pidfile is running the executable
+  /// `exe`.
+  ///
+  /// Returns [`Error::Stale`] if it isn't, since the pid has presumably been
+  /// reused by an unrelated process.
+  #[cfg(target_os = "linux")]
+  pub fn verify_exe<P: AsRef<Path>>(&self, exe: P) -> Result<(), Error> {
+    let expected = std::fs::canonicalize(exe)?;
+    let actual = self.exe()?;
+
+    // The executable may have been replaced, for instance by an upgrade,
+    // since the process was started.
+    let actual = match actual.to_str() {
+      Some(s) => PathBuf::from(s.trim_end_matches(" (deleted)")),
+      None => actual
+    };
+    if actual == expected {
+      Ok(())
+    } else {
+      Err(Error::Stale { pid: self.pid })
+    }
+  }
+}
+
+impl FromStr for PidfileInfo {
+  type Err = ParseError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.trim_start();
+    #[cfg(feature = "serde")]
+    {
+      if s.starts_with('{') {
+        return from_json(s);
+      }
+    }
+    // Without the serde feature, only the pid at the start of a JSON
+    // pidfile is read, so the pidfile is still recognised.
+    let (first, rest) = if s.starts_with('{') {
+      (json_pid(s)?, "")
+    } else {
+      s.split_once('\n').unwrap_or((s, ""))
+    };
+    let mut info = PidfileInfo::new(parse_pid(first)?);
+    for line in rest.lines().map(str::trim).filter(|l| !l.is_empty()) {
+      let (key, value) = match line.split_once('=') {
+        Some((key, value)) if !key.is_empty() => (key, value.trim()),
+        _ => return Err(ParseError::Malformed(line.to_string()))
+      };
+      let malformed = || ParseError::Malformed(line.to_string());
+      match key {
+        KEY_START_TIME => {
+          info.start_time = Some(value.parse().map_err(|_| malformed())?);
+        }
+        KEY_BOOT_ID => info.boot_id = Some(value.to_string()),
+        KEY_EXE => info.exe = Some(PathBuf::from(value)),
+        KEY_HOSTNAME => info.hostname = Some(value.to_string()),
+        KEY_VERSION => info.version = Some(value.to_string()),
+        KEY_PORT => info.port = Some(value.parse().map_err(|_| malformed())?),
+        _ => {
+          info.metadata.insert(key.to_string(), value.to_string());
+        }
+      }
+    }
+    Ok(info)
+  }
+}
+
+/// Check that `key` and `value` can be written as a `key=value` line.
+///
+/// Keys may not be empty or contain `=` or whitespace, and neither keys nor
+/// values may contain line breaks.  Since values are trimmed when parsed,
+/// leading and trailing whitespace in values is not preserved.
+pub(crate) fn check_entry(key: &str, value: &str) -> Result<(), Error> {
+  if key.is_empty() || key.contains(|c: char| c == '=' || c.is_whitespace()) {
+    return Err(Error::InvalidMetadata(format!("invalid key {:?}", key)));
+  }
+  if value.contains(['\n', '\r']) {
+    return Err(Error::InvalidMetadata(format!(
+      "invalid value for key {:?}",
+      key
+    )));
+  }
+  Ok(())
+}
+
+/// Render `info` as a pidfile; the pid on the first line, followed by a
+/// `key=value` line for each piece of information about the process.
+pub(crate) fn to_text(info: &PidfileInfo) -> Result<String, Error> {
+  let mut buf = format!("{}\n", info.pid);
+  let mut entry = |key: &str, value: &str| -> Result<(), Error> {
+    check_entry(key, value)?;
+    buf.push_str(&format!("{}={}\n", key, value));
+    Ok(())
+  };
+  if let Some(start_time) = info.start_time {
+    entry(KEY_START_TIME, &start_time.to_string())?;
+  }
+  if let Some(ref boot_id) = info.boot_id {
+    entry(KEY_BOOT_ID, boot_id)?;
+  }
+  if let Some(ref exe) = info.exe {
+    entry(KEY_EXE, &exe.to_string_lossy())?;
+  }
+  if let Some(ref hostname) = info.hostname {
+    entry(KEY_HOSTNAME, hostname)?;
+  }
+  if let Some(ref version) = info.version {
+    entry(KEY_VERSION, version)?;
+  }
+  if let Some(port) = info.port {
+    entry(KEY_PORT, &port.to_string())?;
+  }
+  for (key, value) in &info.metadata {
+    entry(key, value)?;
+  }
+  Ok(buf)
+}
+
+/// Key of the application-defined metadata in JSON pidfiles.
+#[cfg(feature = "serde")]
+const KEY_METADATA: &str = "metadata";
+
+/// Key of the application-defined state in JSON pidfiles.
+#[cfg(feature = "serde")]
+const KEY_STATE: &str = "state";
+
+/// Render `info`, along with the application-defined `state`, as a JSON
+/// pidfile.
+///
+/// The pid is written first, so it can be found without parsing the whole
+/// document.
+#[cfg(feature = "serde")]
+pub(crate) fn to_json(
+  info: &PidfileInfo,
+  state: &serde_json::Value
+) -> String {
+  use serde_json::{Map, Value};
+
+  let mut doc = Map::new();
+  if let Some(start_time) = info.start_time {
+    doc.insert(KEY_START_TIME.to_string(), start_time.into());
+  }
+  if let Some(ref boot_id) = info.boot_id {
+    doc.insert(KEY_BOOT_ID.to_string(), boot_id.as_str().into());
+  }
+  if let Some(ref exe) = info.exe {
+    doc.insert(KEY_EXE.to_string(), exe.to_string_lossy().into());
+  }
+  if let Some(ref hostname) = info.hostname {
+    doc.insert(KEY_HOSTNAME.to_string(), hostname.as_str().into());
+  }
+  if let Some(ref version) = info.version {
+    doc.insert(KEY_VERSION.to_string(), version.as_str().into());
+  }
+  if let Some(port) = info.port {
+    doc.insert(KEY_PORT.to_string(), port.into());
+  }
+  if !info.metadata.is_empty() {
+    let metadata = info
+      .metadata
+      .iter()
+      .map(|(k, v)| (k.clone(), Value::from(v.as_str())))
+      .collect();
+    doc.insert(KEY_METADATA.to_string(), Value::Object(metadata));
+  }
+  doc.insert(KEY_STATE.to_string(), state.clone());
+
+  // The document always has a state, so it is never empty.
+  let rest = Value::Object(doc).to_string();
+  format!("{{\"pid\":{},{}\n", info.pid, &rest[1..])
+}
+
+/// Parse a JSON pidfile.
+#[cfg(feature = "serde")]
+fn from_json(s: &str) -> Result<PidfileInfo, ParseError> {
+  use serde::de::DeserializeOwned;
+  use serde_json::{Map, Value};
+
+  fn take<T: DeserializeOwned>(
+    doc: &mut Map<String, Value>,
+    key: &str
+  ) -> Result<Option<T>, ParseError> {
+    match doc.remove(key) {
+      Some(value) => serde_json::from_value(value)
+        .map(Some)
+        .map_err(|e| ParseError::Malformed(format!("{}; {}", key, e))),
+      None => Ok(None)
+    }
+  }
+
+  let mut doc: Map<String, Value> = serde_json::from_str(s)
+    .map_err(|e| ParseError::Malformed(e.to_string()))?;
+  let pid = match doc.remove("pid") {
+    Some(Value::Number(pid)) => parse_pid(&pid.to_string())?,
+    Some(pid) => return Err(ParseError::Malformed(pid.to_string())),
+    None => return Err(ParseError::Malformed("no pid".to_string()))
+  };
+  Ok(PidfileInfo {
+    pid,
+    start_time: take(&mut doc, KEY_START_TIME)?,
+    boot_id: take(&mut doc, KEY_BOOT_ID)?,
+    exe: take(&mut doc, KEY_EXE)?,
+    hostname: take(&mut doc, KEY_HOSTNAME)?,
+    version: take(&mut doc, KEY_VERSION)?,
+    port: take(&mut doc, KEY_PORT)?,
+    metadata: take(&mut doc, KEY_METADATA)?.unwrap_or_default(),
+    state: doc.remove(KEY_STATE)
+  })
+}
+
+/// Extract the pid from the start of a JSON pidfile, `{"pid":N,...}`.
+fn json_pid(s: &str) -> Result<&str, ParseError> {
+  let malformed = || {
+    let line = s.lines().next().unwrap_or_default();
+    ParseError::Malformed(line.to_string())
+  };
+  let rest = s.strip_prefix("{\"pid\":").ok_or_else(malformed)?;
+  let end = rest.find([',', '}']).ok_or_else(malformed)?;
+  Ok(&rest[..end])
+}
+
+/// Read the start time of the process `pid` and the id of the current boot,
+/// which together identify the process.
+#[cfg(target_os = "linux")]
+pub(crate) fn read_identity(pid: u32) -> Result<(u64, String), Error> {
+  let start_time = read_start_time(pid)?.ok_or_else(|| {
+    io::Error::new(io::ErrorKind::NotFound, "process has no start time")
+  })?;
+  Ok((start_time, read_boot_id()?))
+}
+
+/// Read the start time of the process `pid`; field 22 of `/proc/<pid>/stat`.
+///
+/// Returns `Ok(None)` if the process does not exist.
+#[cfg(target_os = "linux")]
+fn read_start_time(pid: u32) -> io::Result<Option<u64>> {
+  let stat = match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+    Ok(stat) => stat,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(e)
+  };
+  // The command name, field 2, is in parentheses and may contain spaces, so
+  // count fields from the last closing parenthesis, which precedes field 3.
+  let malformed = || io::Error::new(io::ErrorKind::InvalidData, "bad stat");
+  let (_, rest) = stat.rsplit_once(')').ok_or_else(malformed)?;
+  let field = rest.split_whitespace().nth(22 - 3).ok_or_else(malformed)?;
+  field.parse().map(Some).map_err(|_| malformed())
+}
+
+#[cfg(target_os = "linux")]
+fn read_boot_id() -> io::Result<String> {
+  let id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id")?;
+  Ok(id.trim().to_string())
+}
+
+/// Parse an ascii base-10 process identifier, ignoring surrounding
+/// whitespace.
+pub(crate) fn parse_pid(s: &str) -> Result<u32, ParseError> {
+  let s = s.trim();
+  if s.is_empty() {
+    return Err(ParseError::Empty);
+  }
+  if !s.bytes().all(|b| b.is_ascii_digit()) {
+    return Err(ParseError::Malformed(s.to_string()));
+  }
+  match s.parse::<u32>() {
+    Ok(pid) if pid != 0 && pid <= i32::MAX as u32 => Ok(pid),
+    _ => Err(ParseError::OutOfRange(s.to_string()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_valid_pid() {
+    assert_eq!(parse_pid("1"), Ok(1));
+    assert_eq!(parse_pid(" 42 "), Ok(42));
+    assert_eq!(parse_pid("2147483647"), Ok(2147483647));
+  }
+
+  #[test]
+  fn parse_empty_pid() {
+    assert_eq!(parse_pid(""), Err(ParseError::Empty));
+    assert_eq!(parse_pid(" \t\n"), Err(ParseError::Empty));
+  }
+
+  #[test]
+  fn parse_out_of_range_pid() {
+    assert_eq!(parse_pid("0"), Err(ParseError::OutOfRange("0".to_string())));
+    assert_eq!(
+      parse_pid("2147483648"),
+      Err(ParseError::OutOfRange("2147483648".to_string()))
+    );
+    assert_eq!(
+      parse_pid("99999999999"),
+      Err(ParseError::OutOfRange("99999999999".to_string()))
+    );
+  }
+
+  #[test]
+  fn parse_malformed_pid() {
+    for s in &["abc", "12a", "-1", "+1", "1 2", "0x10"] {
+      assert_eq!(parse_pid(s), Err(ParseError::Malformed(s.to_string())));
+    }
+  }
+
+  #[test]
+  fn parse_info() {
+    let info: PidfileInfo = "1234\n".parse().unwrap();
+    assert_eq!(info.pid(), 1234);
+    assert!(info.metadata().is_empty());
+
+    let info: PidfileInfo = "1234".parse().unwrap();
+    assert_eq!(info.pid(), 1234);
+
+    let info: PidfileInfo = "\n 1234 \r\n\n".parse().unwrap();
+    assert_eq!(info.pid(), 1234);
+  }
+
+  #[test]
+  fn parse_json_info() {
+    let info: PidfileInfo =
+      "{\"pid\":1234,\"metadata\":{}}\n".parse().unwrap();
+    assert_eq!(info.pid(), 1234);
+
+    let info: PidfileInfo = "{\"pid\":1234}".parse().unwrap();
+    assert_eq!(info.pid(), 1234);
+
+    assert!("{\"pid\":\"1234\"}".parse::<PidfileInfo>().is_err());
+    assert!("{\"port\":80}".parse::<PidfileInfo>().is_err());
+  }
+
+  #[test]
+  fn parse_invalid_info() {
+    assert_eq!("".parse::<PidfileInfo>(), Err(ParseError::Empty));
+    assert_eq!("\n\n".parse::<PidfileInfo>(), Err(ParseError::Empty));
+    assert_eq!(
+      "0\n".parse::<PidfileInfo>(),
+      Err(ParseError::OutOfRange("0".to_string()))
+    );
+    assert_eq!(
+      "pid\n".parse::<PidfileInfo>(),
+      Err(ParseError::Malformed("pid".to_string()))
+    );
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
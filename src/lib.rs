@@ -3,51 +3,771 @@
 //! Creates a pidfile on creation and automatically remove it on termination.
 //!
 //! ```
-//! fn main() {
-//!   let pidfile = Pidfile::new("myserver.pid");
-//!   // .. run server ..
+//! use qpidfile::Pidfile;
 //!
-//!   // On termination the Pidfile will automatically be removed.
-//! }
+//! let pidfile = Pidfile::new("myserver.pid").expect("no pidfile");
+//! // .. run server ..
+//!
+//! // On termination the Pidfile will automatically be removed.
+//! ```
+//!
+//! The pidfile is kept open and exclusively locked for as long as the
+//! [`Pidfile`] object is alive.  If another process already holds the lock
+//! [`Pidfile::new()`] will fail rather than overwrite the other process'
+//! pidfile.
+//!
+//! A pidfile which is not locked, but which contains the pid of a process
+//! which no longer exists, is considered stale.  Stale pidfiles are reclaimed
+//! by [`Pidfile::new()`]; use [`Pidfile::with_policy()`] to have them
+//! reported as errors instead.
+//!
+//! Errors which occur while removing the pidfile on drop are written to
+//! stderr by default; use [`set_drop_error_hook()`] to route them elsewhere,
+//! or [`Pidfile::remove()`] to handle them explicitly.
+//!
+//! Use [`PidfileBuilder`] to control the pidfile's permissions and ownership,
+//! whether it should be locked and removed on drop, and more.
+//!
+//! Processes which need to know which process owns a pidfile can use
+//! [`Pidfile::read()`]:
+//!
+//! ```no_run
+//! use qpidfile::Pidfile;
+//!
+//! let info = Pidfile::read("myserver.pid").expect("no server running");
+//! println!("server pid is {}", info.pid());
 //! ```
 //!
 //! Be mindful of the [`Drop`] trait caveats; for instance calling
-//! [`std::process::exit()`] will cause Drop traits not to run.
+//! [`std::process::exit()`] will cause Drop traits not to run.  The
+//! [`cleanup`] module can be used to remove pidfiles on exit and on
+//! termination signals as well.
+//!
+//! Programs which can't manage their own pidfile can be run using [`run()`],
+//! which holds the pidfile on their behalf for as long as they are running.
 //!
 //! [`std::process::exit()`]: https://doc.rust-lang.org/std/process/fn.exit.html
 //! [`Drop`]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+mod builder;
+pub mod cleanup;
+mod ctl;
+pub mod daemonize;
+mod err;
+mod hook;
+mod info;
+pub mod notify;
+#[cfg(target_os = "linux")]
+mod pidfd;
+mod rundir;
+mod signal;
+mod supervise;
+mod sys;
+
 use std::io::prelude::*;
+use std::io::{self, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::process;
-use std::fs::File;
+use std::fs::{DirBuilder, File, OpenOptions, Permissions};
+use std::os::unix::fs::{chown, fchown, DirBuilderExt, MetadataExt};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+pub use builder::PidfileBuilder;
+pub use ctl::{clean_stale, stop, wait_for_exit, StopOutcome, StopPolicy};
+pub use err::Error;
+pub use hook::{clear_drop_error_hook, set_drop_error_hook};
+pub use info::{ParseError, PidfileInfo};
+#[cfg(target_os = "linux")]
+pub use pidfd::PidFd;
+pub use rundir::pidfile_path;
+pub use signal::Signal;
+pub use supervise::{run, RunOptions};
+
+/// Number of times to attempt to read the pid of a process holding the lock
+/// before giving up.  The holder may be in the middle of writing its pid.
+const READ_RETRIES: usize = 5;
+
+/// What to do when encountering a stale pidfile; i.e. one which contains the
+/// pid of a process which no longer exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StalePolicy {
+  /// Silently take over the stale pidfile.
+  #[default]
+  Reclaim,
+
+  /// Fail with [`Error::Stale`].
+  Fail
+}
 
 pub struct Pidfile {
-  fname: PathBuf
+  fname: PathBuf,
+  /// Keep the file open; the exclusive lock lives as long as the descriptor.
+  file: File,
+  /// The pid written to the file.
+  pid: u32,
+  /// The process which owns the pidfile; normally the one whose pid is
+  /// written to it.
+  owner: u32,
+  /// Device and inode numbers of the pidfile, used to detect if it has been
+  /// replaced.
+  dev: u64,
+  ino: u64,
+  remove: bool,
+  /// Options used to create the pidfile, retained for rewriting it.
+  opts: PidfileBuilder,
+  /// Directories created for the pidfile, outermost first.
+  dirs: Vec<PathBuf>,
+  /// Slot in the cleanup table, if the pidfile has been recorded in it.
+  slot: Option<usize>
 }
 
 impl Drop for Pidfile {
   fn drop(&mut self) {
-    if let Err(e) = std::fs::remove_file(&self.fname) {
-      eprintln!("Unable to remove pidfile {:?}; {}", self.fname, e);
+    // A forked process which hasn't claimed the pidfile using
+    // update_pid() must not remove its parent's (or child's) pidfile.
+    if self.owner != process::id() {
+      return;
     }
+    if self.remove {
+      if let Err(e) = self.unlink() {
+        hook::report(&self.fname, &e);
+      }
+    }
+    self.unregister();
   }
 }
 
 /// Representation of a "pidfile", which contains the process identifier, of
-/// the current process, in ascii base-10 format.
+/// the current process, in ascii base-10 format followed by a newline.
 ///
 /// A [`Drop`] trait is used to automatically remove the pidfile on
-/// termination.
+/// termination.  The pidfile is only removed if it still is the same file,
+/// containing the same pid, as when it was created; a pidfile which has been
+/// replaced by an operator or another process is left alone.
 ///
 /// [`Drop`]: https://doc.rust-lang.org/std/ops/trait.Drop.html
 impl Pidfile {
-  /// (Over)write the file specified in the parameter fname with the process
-  /// idenfier of the current process.
-  pub fn new<P: AsRef<Path>>(fname: P) -> std::io::Result<Self> {
-    let mut file = File::create(fname.as_ref())?;
-    let pidstr = format!("{}", process::id());
-    file.write_all(pidstr.as_bytes())?;
-    Ok(Pidfile { fname: fname.as_ref().to_path_buf() })
+  /// Lock and (over)write the file specified in the parameter fname with the
+  /// process idenfier of the current process.
+  ///
+  /// The pid is written to a temporary file in the same directory, which is
+  /// then renamed to fname, so readers will only ever see a complete pidfile.
+  ///
+  /// If another process holds the lock on the pidfile
+  /// [`Error::AlreadyRunning`] is returned.  If the lock is held but the pid
+  /// could not be read an [`Error::Io`] of kind [`io::ErrorKind::WouldBlock`]
+  /// is returned.
+  ///
+  /// If the pidfile isn't locked, but contains the pid of another live
+  /// process, [`Error::AlreadyRunning`] is returned as well.  Stale pidfiles
+  /// are reclaimed.
+  pub fn new<P: AsRef<Path>>(fname: P) -> Result<Self, Error> {
+    Self::with_policy(fname, StalePolicy::Reclaim)
+  }
+
+  /// Same as [`Pidfile::new()`], but use `policy` to determine what to do if
+  /// the pidfile is stale.
+  pub fn with_policy<P: AsRef<Path>>(
+    fname: P,
+    policy: StalePolicy
+  ) -> Result<Self, Error> {
+    PidfileBuilder::new().stale_policy(policy).create(fname)
+  }
+
+  pub(crate) fn create(
+    fname: &Path,
+    opts: &PidfileBuilder
+  ) -> Result<Self, Error> {
+    let dirs = match fname.parent() {
+      Some(dir) if opts.create_dirs && !dir.as_os_str().is_empty() => {
+        create_dirs(dir, opts)?
+      }
+      _ => Vec::new()
+    };
+    match Self::create_file(fname, opts) {
+      Ok(mut pidfile) => {
+        pidfile.dirs = dirs;
+        pidfile.slot = cleanup::register(
+          fname,
+          pidfile.owner,
+          pidfile.pid,
+          pidfile.dev,
+          pidfile.ino,
+          opts.remove_on_drop
+        );
+        // On failure the pidfile is dropped, and thus removed.
+        if opts.notify {
+          notify::notify_mainpid(pidfile.pid)?;
+        }
+        Ok(pidfile)
+      }
+      Err(e) => {
+        remove_dirs(&dirs);
+        Err(e)
+      }
+    }
+  }
+
+  fn create_file(fname: &Path, opts: &PidfileBuilder) -> Result<Self, Error> {
+    loop {
+      let mut existing = open_existing(fname, opts.lock)?;
+
+      if existing.is_some() && opts.exclusive {
+        return Err(Error::Exists(fname.to_path_buf()));
+      }
+
+      // The lock being free does not necessarily mean the pidfile is stale;
+      // it may have been written by a process which doesn't use locking.
+      if let Some(ref mut file) = existing {
+        if let Some(info) = read_info(file)? {
+          let pid = info.pid();
+          if pid != process::id() {
+            if info.is_alive()? {
+              return Err(Error::AlreadyRunning { pid });
+            }
+            if opts.stale_policy == StalePolicy::Fail {
+              return Err(Error::Stale { pid });
+            }
+          }
+        }
+      }
+
+      // The old file, if any, must remain locked until it has been replaced.
+      match replace(fname, existing.is_some(), opts, process::id())? {
+        Some(file) => {
+          let md = file.metadata()?;
+          return Ok(Pidfile {
+            fname: fname.to_path_buf(),
+            file,
+            pid: process::id(),
+            owner: process::id(),
+            dev: md.dev(),
+            ino: md.ino(),
+            remove: opts.remove_on_drop,
+            opts: opts.clone(),
+            dirs: Vec::new(),
+            slot: None
+          });
+        }
+        None if opts.exclusive => {
+          return Err(Error::Exists(fname.to_path_buf()));
+        }
+        None => {}
+      }
+    }
+  }
+
+  /// Rewrite the pidfile with the pid of the current process.
+  ///
+  /// This is intended to be called in a child process after a `fork()`, so
+  /// the pidfile created by the parent names the child instead.  The
+  /// [`Drop`] implementation does nothing in any process other than the one
+  /// whose pid is in the pidfile, so a child which does not call this
+  /// function will leave the pidfile alone.
+  ///
+  /// Once the child has claimed the pidfile the parent's copy of the object
+  /// no longer owns it; the parent should terminate without dropping it (for
+  /// instance using [`std::process::exit()`]), or dropping it will report
+  /// [`Error::NotOwner`].
+  ///
+  /// [`Drop`]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+  /// [`std::process::exit()`]: https://doc.rust-lang.org/std/process/fn.exit.html
+  pub fn update_pid(&mut self) -> Result<(), Error> {
+    let pid = process::id();
+    if pid == self.pid && pid == self.owner {
+      return Ok(());
+    }
+    self.rewrite(pid, pid)
+  }
+
+  /// Rewrite the pidfile with the pid of the child process `pid`, which the
+  /// current process supervises.
+  ///
+  /// The current process remains the owner of the pidfile; it holds the
+  /// lock and removes the pidfile when the object is dropped.
+  pub(crate) fn set_child_pid(&mut self, pid: u32) -> Result<(), Error> {
+    self.rewrite(process::id(), pid)
+  }
+
+  /// Replace the pidfile with one containing `pid`, owned by the process
+  /// `owner`.
+  fn rewrite(&mut self, owner: u32, pid: u32) -> Result<(), Error> {
+    self.check_owner()?;
+    // replace() only returns None when there's no existing file.
+    let file = replace(&self.fname, true, &self.opts, pid)?
+      .ok_or_else(|| Error::Missing(self.fname.clone()))?;
+    let md = file.metadata()?;
+    self.file = file;
+    self.pid = pid;
+    self.owner = owner;
+    self.dev = md.dev();
+    self.ino = md.ino();
+    if let Some(slot) = self.slot {
+      cleanup::update(slot, owner, pid, self.dev, self.ino);
+    }
+    if self.opts.notify {
+      notify::notify_mainpid(pid)?;
+    }
+    Ok(())
+  }
+
+  /// Tell the service manager that the service has finished starting up,
+  /// and that the process named in the pidfile is its main process.
+  ///
+  /// Returns `Ok(false)` if the process isn't running under a service
+  /// manager which supports notifications.  See the [`notify`] module.
+  pub fn notify_ready(&self) -> Result<bool, Error> {
+    notify::notify(&format!("READY=1\nMAINPID={}", self.pid))
+  }
+
+  /// Remove the pidfile now, rather than when the object is dropped.
+  ///
+  /// Unlike the [`Drop`] implementation, errors are returned to the caller
+  /// instead of being reported via the drop error hook.  The pidfile is
+  /// removed regardless of whether the object was configured to remove it
+  /// on drop.
+  ///
+  /// Like the [`Drop`] implementation, a forked process which hasn't claimed
+  /// the pidfile using [`Pidfile::update_pid()`] leaves it alone; it gets
+  /// [`Error::NotOwner`] instead.
+  ///
+  /// [`Drop`]: https://doc.rust-lang.org/std/ops/trait.Drop.html
+  pub fn remove(mut self) -> Result<(), Error> {
+    self.remove = false;
+    if self.owner != process::id() {
+      return Err(Error::NotOwner(self.fname.clone()));
+    }
+    let res = self.unlink();
+    self.unregister();
+    res
+  }
+
+  /// Whether the pidfile is recorded in the process-wide registry, and will
+  /// thus be listed and removed by the [`cleanup`] functions.
+  ///
+  /// The registry can hold a limited number of pidfiles; those created while
+  /// it is full are not recorded.
+  pub fn is_registered(&self) -> bool {
+    self.slot.is_some()
+  }
+
+  fn unregister(&mut self) {
+    if let Some(slot) = self.slot.take() {
+      cleanup::unregister(slot);
+    }
+  }
+
+  fn unlink(&mut self) -> Result<(), Error> {
+    // Already removed via the registry.
+    if self.slot.is_some_and(cleanup::is_removed) {
+      return Ok(());
+    }
+    // Remove the file while the lock is still held, so no other process can
+    // lock the file just before it is unlinked.
+    self.check_owner()?;
+    std::fs::remove_file(&self.fname)?;
+    if self.opts.remove_dirs {
+      remove_dirs(&self.dirs);
+    }
+    Ok(())
+  }
+
+  /// Make sure the pidfile still is the file which was created by this
+  /// object, and that it still contains this object's pid.
+  ///
+  /// Returns [`Error::NotOwner`] if the file has been replaced or rewritten.
+  fn check_owner(&mut self) -> Result<(), Error> {
+    let md = match std::fs::metadata(&self.fname) {
+      Ok(md) => md,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        return Err(Error::Missing(self.fname.clone()));
+      }
+      Err(e) => return Err(Error::Io(e))
+    };
+    if md.dev() != self.dev || md.ino() != self.ino {
+      return Err(Error::NotOwner(self.fname.clone()));
+    }
+    if read_pid(&mut self.file)? != Some(self.pid) {
+      return Err(Error::NotOwner(self.fname.clone()));
+    }
+    Ok(())
+  }
+
+  /// Read and parse the pidfile `fname`, without locking or modifying it.
+  pub fn read<P: AsRef<Path>>(fname: P) -> Result<PidfileInfo, Error> {
+    PidfileInfo::read(fname)
+  }
+
+  /// Send the signal `sig` to the process named in the pidfile `fname`.
+  ///
+  /// Returns the pid of the process which was signaled.  See
+  /// [`PidfileInfo::signal()`] for details.
+  ///
+  /// ```no_run
+  /// use qpidfile::{Pidfile, Signal};
+  ///
+  /// // Ask the server to reload its configuration.
+  /// Pidfile::signal("myserver.pid", Signal::Hup).expect("unable to signal");
+  /// ```
+  pub fn signal<P: AsRef<Path>>(fname: P, sig: Signal) -> Result<u32, Error> {
+    let info = PidfileInfo::read(fname)?;
+    info.signal(sig)?;
+    Ok(info.pid())
+  }
+}
+
+/// Create the directory `dir` and any missing parents, returning the
+/// directories which were created, outermost first.
+fn create_dirs(
+  dir: &Path,
+  opts: &PidfileBuilder
+) -> Result<Vec<PathBuf>, Error> {
+  let missing: Vec<&Path> = dir
+    .ancestors()
+    .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+    .collect();
+
+  let mut created = Vec::new();
+  let mut builder = DirBuilder::new();
+  builder.mode(opts.dir_mode.unwrap_or(0o777));
+  for d in missing.into_iter().rev() {
+    match builder.create(d) {
+      Ok(()) => {}
+      // Someone else beat us to it; it's theirs.
+      Err(e) if e.kind() == io::ErrorKind::AlreadyExists && d.is_dir() => {
+        continue;
+      }
+      Err(e) => {
+        remove_dirs(&created);
+        return Err(Error::Io(e));
+      }
+    }
+    created.push(d.to_path_buf());
+    if let Err(e) = set_dir_attrs(d, opts) {
+      remove_dirs(&created);
+      return Err(Error::Io(e));
+    }
+  }
+  Ok(created)
+}
+
+fn set_dir_attrs(dir: &Path, opts: &PidfileBuilder) -> io::Result<()> {
+  if let Some(mode) = opts.dir_mode {
+    std::fs::set_permissions(dir, Permissions::from_mode(mode))?;
+  }
+  if opts.dir_uid.is_some() || opts.dir_gid.is_some() {
+    chown(dir, opts.dir_uid, opts.dir_gid)?;
+  }
+  Ok(())
+}
+
+/// Remove the directories in `dirs`, innermost first, stopping at the first
+/// one which can't be removed; for instance because it isn't empty.
+fn remove_dirs(dirs: &[PathBuf]) {
+  for d in dirs.iter().rev() {
+    if std::fs::remove_dir(d).is_err() {
+      break;
+    }
+  }
+}
+
+/// Remove the pidfile `fname` if it is not locked and still names the process
+/// `pid`, which is expected to have exited.
+///
+/// Returns `Ok(true)` if the pidfile was removed.
+pub(crate) fn remove_stale(fname: &Path, pid: u32) -> Result<bool, Error> {
+  let mut file = match open_existing(fname, true) {
+    Ok(Some(file)) => file,
+    Ok(None) | Err(Error::AlreadyRunning { .. }) => return Ok(false),
+    Err(e) => return Err(e)
+  };
+  if read_pid(&mut file)? != Some(pid) {
+    return Ok(false);
+  }
+  // Unlink while the lock is held.
+  std::fs::remove_file(fname)?;
+  Ok(true)
+}
+
+/// Check whether the pidfile `fname` is locked; i.e. whether the process
+/// which created it is still alive and holding it.
+#[cfg(target_os = "linux")]
+pub(crate) fn is_locked(fname: &Path) -> Result<bool, Error> {
+  let file = match File::open(fname) {
+    Ok(file) => file,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+    Err(e) => return Err(Error::Io(e))
+  };
+  // The lock, if we got it, is released when the file is closed.
+  Ok(!sys::try_lock_exclusive(file.as_raw_fd())?)
+}
+
+/// Open, and if `lock` is set exclusively lock, an existing file at `fname`.
+///
+/// Returns `Ok(None)` if there's no file at `fname`.
+///
+/// Guards against the file being removed or replaced between opening and
+/// locking it by verifying that the path still refers to the locked file.
+fn open_existing(fname: &Path, lock: bool) -> Result<Option<File>, Error> {
+  let mut retries = READ_RETRIES;
+  loop {
+    let mut file = match OpenOptions::new().read(true).write(true).open(fname)
+    {
+      Ok(file) => file,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(Error::Io(e))
+    };
+    if !lock {
+      return Ok(Some(file));
+    }
+
+    if !sys::try_lock_exclusive(file.as_raw_fd())? {
+      if let Some(pid) = read_pid(&mut file)? {
+        return Err(Error::AlreadyRunning { pid });
+      }
+      if retries == 0 {
+        return Err(Error::Io(io::Error::new(
+          io::ErrorKind::WouldBlock,
+          "pidfile is locked by another process"
+        )));
+      }
+      retries -= 1;
+      thread::sleep(Duration::from_millis(5));
+      continue;
+    }
+
+    // The file may have been unlinked, or replaced, by its previous owner
+    // after we opened it; in which case the lock is worthless.
+    let locked = file.metadata()?;
+    match std::fs::metadata(fname) {
+      Ok(md) if md.dev() == locked.dev() && md.ino() == locked.ino() => {
+        return Ok(Some(file));
+      }
+      Ok(_) => continue,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(Error::Io(e))
+    }
+  }
+}
+
+/// Write the pid `pid` to a locked temporary file next to `fname` and move it
+/// into place, so readers never observe a partially written pidfile.
+///
+/// If `exists` is `true` the (locked) file at `fname` is atomically replaced.
+/// Otherwise the new file is linked into place, and `Ok(None)` is returned if
+/// another process managed to create `fname` first.
+fn replace(
+  fname: &Path,
+  exists: bool,
+  opts: &PidfileBuilder,
+  pid: u32
+) -> Result<Option<File>, Error> {
+  let contents = contents(opts, pid)?;
+  let tmpname = tmpname(fname);
+  let res = write_tmp(&tmpname, opts, &contents).and_then(|file| {
+    if exists {
+      std::fs::rename(&tmpname, fname)?;
+    } else {
+      match std::fs::hard_link(&tmpname, fname) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
+        Err(e) => return Err(e)
+      }
+    }
+    sync_dir(fname)?;
+    Ok(Some(file))
+  });
+  if !exists || res.is_err() {
+    let _ = std::fs::remove_file(&tmpname);
+  }
+  Ok(res?)
+}
+
+fn write_tmp(
+  tmpname: &Path,
+  opts: &PidfileBuilder,
+  contents: &str
+) -> io::Result<File> {
+  // Remove any leftovers from a previous process with the same pid, so the
+  // file mode is applied to a fresh file.
+  let _ = std::fs::remove_file(tmpname);
+  let mut file = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create_new(true)
+    .mode(opts.mode.unwrap_or(0o666))
+    .open(tmpname)?;
+  if let Some(mode) = opts.mode {
+    file.set_permissions(Permissions::from_mode(mode))?;
+  }
+  if opts.uid.is_some() || opts.gid.is_some() {
+    fchown(&file, opts.uid, opts.gid)?;
+  }
+  if opts.lock && !sys::try_lock_exclusive(file.as_raw_fd())? {
+    return Err(io::Error::new(
+      io::ErrorKind::WouldBlock,
+      "temporary pidfile is locked by another process"
+    ));
+  }
+  file.write_all(contents.as_bytes())?;
+  file.sync_all()?;
+  Ok(file)
+}
+
+/// Name of the temporary file used while writing the pidfile `fname`.
+fn tmpname(fname: &Path) -> PathBuf {
+  let mut name = std::ffi::OsString::from(".");
+  if let Some(base) = fname.file_name() {
+    name.push(base);
+  }
+  name.push(format!(".{}.tmp", process::id()));
+  fname.with_file_name(name)
+}
+
+/// Make sure the directory entry for `fname` has reached stable storage.
+fn sync_dir(fname: &Path) -> io::Result<()> {
+  let dir = match fname.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new(".")
+  };
+  File::open(dir)?.sync_all()
+}
+
+/// The contents of the pidfile for the process `pid`; either the current
+/// process or a child of it.
+fn contents(opts: &PidfileBuilder, pid: u32) -> Result<String, Error> {
+  for (key, value) in &opts.metadata {
+    if info::RESERVED_KEYS.contains(&key.as_str()) {
+      return Err(Error::InvalidMetadata(format!("reserved key {:?}", key)));
+    }
+    info::check_entry(key, value)?;
+  }
+  let mut info = PidfileInfo::new(pid);
+  #[cfg(target_os = "linux")]
+  {
+    if opts.identity {
+      let (start_time, boot_id) = info::read_identity(pid)?;
+      info.start_time = Some(start_time);
+      info.boot_id = Some(boot_id);
+    }
+  }
+  if opts.exe {
+    info.exe = Some(exe_of(pid)?);
+  }
+  if opts.hostname {
+    info.hostname = Some(sys::hostname()?);
+  }
+  info.version = opts.version.clone();
+  info.port = opts.port;
+  info.metadata = opts.metadata.clone();
+  #[cfg(feature = "serde")]
+  {
+    if let Some(ref state) = opts.state {
+      let state = state.as_ref().map_err(|e| {
+        Error::InvalidMetadata(format!("unable to serialize state; {}", e))
+      })?;
+      return Ok(info::to_json(&info, state));
+    }
+  }
+  info::to_text(&info)
+}
+
+/// The path of the executable the process `pid` is running.  Only supported
+/// for other processes than the current one on Linux.
+fn exe_of(pid: u32) -> io::Result<PathBuf> {
+  if pid == process::id() {
+    return std::env::current_exe();
+  }
+  #[cfg(target_os = "linux")]
+  {
+    std::fs::read_link(format!("/proc/{}/exe", pid))
+  }
+  #[cfg(not(target_os = "linux"))]
+  {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "unable to determine the executable of another process"
+    ))
+  }
+}
+
+/// Read the information stored in an open pidfile, if it is valid.
+fn read_info(file: &mut File) -> io::Result<Option<PidfileInfo>> {
+  let mut buf = Vec::new();
+  file.seek(SeekFrom::Start(0))?;
+  file.read_to_end(&mut buf)?;
+  Ok(String::from_utf8_lossy(&buf).parse().ok())
+}
+
+/// Read the pid stored in an open pidfile, if it contains a valid one.
+fn read_pid(file: &mut File) -> io::Result<Option<u32>> {
+  Ok(read_info(file)?.map(|info| info.pid()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn invalid(opts: &PidfileBuilder) -> bool {
+    matches!(contents(opts, 1234), Err(Error::InvalidMetadata(_)))
+  }
+
+  #[test]
+  fn contents_round_trip() {
+    let mut opts = PidfileBuilder::new();
+    opts
+      .version("1.2.3")
+      .port(8080)
+      .metadata("role", "primary")
+      .metadata("config", "/etc/my server.conf")
+      .metadata("empty", "")
+      .metadata("expr", "a=b");
+    let buf = contents(&opts, 1234).unwrap();
+    assert!(buf.starts_with("1234\n"));
+
+    let info: PidfileInfo = buf.parse().unwrap();
+    assert_eq!(info.pid(), 1234);
+    assert_eq!(info.version(), Some("1.2.3"));
+    assert_eq!(info.port(), Some(8080));
+    assert_eq!(info.get("role"), Some("primary"));
+    assert_eq!(info.get("config"), Some("/etc/my server.conf"));
+    assert_eq!(info.get("empty"), Some(""));
+    assert_eq!(info.get("expr"), Some("a=b"));
+    assert_eq!(info.metadata().len(), 4);
+    assert_eq!(info.get("version"), None);
+  }
+
+  #[test]
+  fn contents_without_metadata() {
+    let buf = contents(&PidfileBuilder::new(), 1234).unwrap();
+    assert_eq!(buf, "1234\n");
+    let info: PidfileInfo = buf.parse().unwrap();
+    assert_eq!(info.pid(), 1234);
+    assert!(info.metadata().is_empty());
+  }
+
+  #[test]
+  fn reserved_keys_are_rejected() {
+    for key in info::RESERVED_KEYS {
+      assert!(invalid(PidfileBuilder::new().metadata(*key, "x")), "{}", key);
+    }
+  }
+
+  #[test]
+  fn invalid_keys_are_rejected() {
+    for key in &["", "a=b", "a b", "a\tb", "a\nb", " a"] {
+      assert!(invalid(PidfileBuilder::new().metadata(*key, "x")), "{:?}", key);
+    }
+  }
+
+  #[test]
+  fn invalid_values_are_rejected() {
+    for value in &["a\nb", "a\rb", "\n"] {
+      assert!(invalid(PidfileBuilder::new().metadata("key", *value)));
+      assert!(invalid(PidfileBuilder::new().version(*value)));
+    }
   }
 }
 
@@ -0,0 +1,64 @@
+//! Minimal implementation of systemd's service notification protocol.
+//!
+//! Messages are sent as datagrams to the Unix socket named in the
+//! `NOTIFY_SOCKET` environment variable; see `sd_notify(3)`.  If the
+//! variable is not set the process is not running under a service manager
+//! which supports notifications, and messages are silently discarded.
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+use crate::Error;
+
+/// Send the notification `state`, for instance `"READY=1"`, to the service
+/// manager.
+///
+/// Returns `Ok(false)` if `NOTIFY_SOCKET` isn't set.
+pub fn notify(state: &str) -> Result<bool, Error> {
+  let path = match env::var_os("NOTIFY_SOCKET") {
+    Some(path) if !path.is_empty() => path,
+    _ => return Ok(false)
+  };
+  let sock = UnixDatagram::unbound()?;
+  let bytes = path.as_encoded_bytes();
+
+  // A leading '@' denotes a socket in Linux' abstract namespace.
+  if let Some(name) = bytes.strip_prefix(b"@") {
+    send_abstract(&sock, name, state)?;
+  } else {
+    sock.send_to(state.as_bytes(), path)?;
+  }
+  Ok(true)
+}
+
+/// Tell the service manager that `pid` is the service's main process.
+///
+/// Unless the service is configured with `NotifyAccess=all` the service
+/// manager only accepts this from the current main process.
+pub fn notify_mainpid(pid: u32) -> Result<bool, Error> {
+  notify(&format!("MAINPID={}", pid))
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(
+  sock: &UnixDatagram,
+  name: &[u8],
+  state: &str
+) -> io::Result<()> {
+  use std::os::linux::net::SocketAddrExt;
+  use std::os::unix::net::SocketAddr;
+
+  let addr = SocketAddr::from_abstract_name(name)?;
+  sock.send_to_addr(state.as_bytes(), &addr)?;
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_: &UnixDatagram, _: &[u8], _: &str) -> io::Result<()> {
+  Err(io::Error::new(
+    io::ErrorKind::Unsupported,
+    "abstract sockets are not supported on this platform"
+  ))
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,154 @@
+//! Race-free process handles obtained from pidfiles (Linux only).
+//!
+//! A pid read from a pidfile may be reused by an unrelated process at any
+//! time after the original process has terminated.  A [`PidFd`] refers to a
+//! specific process rather than a number, so once it has been obtained
+//! signals can not reach the wrong process.
+use std::io;
+use std::os::raw::c_int;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{sys, Error, PidfileInfo, Signal};
+
+/// Number of times to retry if the pidfile is rewritten while the handle is
+/// being opened.
+const OPEN_RETRIES: usize = 5;
+
+/// A handle to the process named in a pidfile.
+#[derive(Debug)]
+pub struct PidFd {
+  fd: OwnedFd,
+  pid: u32
+}
+
+impl PidFd {
+  /// Open a handle to the process named in the pidfile `fname`.
+  ///
+  /// After the handle has been opened the pidfile is read again to verify
+  /// that it still names the same process, and that the process still owns
+  /// the pidfile, so the handle can not refer to a process which reused the
+  /// pid after the pidfile's owner terminated.  If the pidfile records the
+  /// process' identity (see [`PidfileBuilder::record_identity()`]) the
+  /// process must match it; otherwise the pidfile must be locked by its
+  /// owner.
+  ///
+  /// Returns [`Error::Stale`] if the process does not exist, or if it can't
+  /// be verified to be the pidfile's owner; for instance because the pidfile
+  /// was created without locking and without recording the identity.
+  ///
+  /// [`PidfileBuilder::record_identity()`]:
+  /// crate::PidfileBuilder::record_identity
+  pub fn from_pidfile<P: AsRef<Path>>(fname: P) -> Result<Self, Error> {
+    let fname = fname.as_ref();
+    let mut info = PidfileInfo::read(fname)?;
+    for _ in 0..OPEN_RETRIES {
+      let pidfd = Self::open(info.pid())?;
+      let current = PidfileInfo::read(fname)?;
+      if current.pid() == info.pid() {
+        // Make sure the handle doesn't refer to an unrelated process which
+        // reused the pid; either by its recorded identity, or by the owner
+        // still holding the lock on the pidfile.
+        let owned =
+          if current.start_time().is_some() || current.boot_id().is_some() {
+            current.is_alive()?
+          } else {
+            crate::is_locked(fname)?
+          };
+        if !owned {
+          return Err(Error::Stale { pid: info.pid() });
+        }
+        return Ok(pidfd);
+      }
+      // The pidfile was rewritten; try again with the new process.
+      info = current;
+    }
+    Err(Error::Io(io::Error::new(
+      io::ErrorKind::Interrupted,
+      "pidfile keeps changing"
+    )))
+  }
+
+  /// Open a handle to the process `pid`.
+  pub fn open(pid: u32) -> Result<Self, Error> {
+    match sys::pidfd_open(pid) {
+      Ok(fd) => Ok(PidFd { fd, pid }),
+      Err(e) => Err(map_err(e, pid))
+    }
+  }
+
+  /// The process identifier of the process the handle refers to.
+  pub fn pid(&self) -> u32 {
+    self.pid
+  }
+
+  /// Send the signal `sig` to the process.
+  ///
+  /// Returns [`Error::Stale`] if the process has terminated.
+  pub fn signal(&self, sig: Signal) -> Result<(), Error> {
+    sys::pidfd_send_signal(self.fd.as_raw_fd(), sig.as_raw())
+      .map_err(|e| map_err(e, self.pid))
+  }
+
+  /// Check whether the process is still running.
+  pub fn is_alive(&self) -> Result<bool, Error> {
+    Ok(!sys::poll_readable(self.fd.as_raw_fd(), 0)?)
+  }
+
+  /// Wait for the process to terminate.
+  ///
+  /// Returns `Ok(true)` if the process terminated within `timeout`, which
+  /// can be `None` to wait indefinitely.  So does a timeout which is too
+  /// large to represent a deadline.
+  pub fn wait(&self, timeout: Option<Duration>) -> Result<bool, Error> {
+    let deadline = timeout.and_then(|t| Instant::now().checked_add(t));
+    loop {
+      let ms = match deadline {
+        Some(d) => ceil_millis(d.saturating_duration_since(Instant::now())),
+        None => -1
+      };
+      match sys::poll_readable(self.fd.as_raw_fd(), ms) {
+        Ok(true) => return Ok(true),
+        Ok(false) => {
+          if deadline.is_some_and(|d| Instant::now() >= d) {
+            return Ok(false);
+          }
+        }
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => return Err(Error::Io(e))
+      }
+    }
+  }
+}
+
+impl AsRawFd for PidFd {
+  fn as_raw_fd(&self) -> RawFd {
+    self.fd.as_raw_fd()
+  }
+}
+
+impl AsFd for PidFd {
+  fn as_fd(&self) -> BorrowedFd<'_> {
+    self.fd.as_fd()
+  }
+}
+
+/// Convert `d` to a poll(2) timeout, rounding up so the caller doesn't spin
+/// during the last millisecond.
+fn ceil_millis(d: Duration) -> c_int {
+  let ms = d.as_nanos().div_ceil(1_000_000);
+  ms.min(c_int::MAX as u128) as c_int
+}
+
+fn map_err(e: io::Error, pid: u32) -> Error {
+  if e.raw_os_error() == Some(sys::ESRCH) {
+    Error::Stale { pid }
+  } else if e.kind() == io::ErrorKind::PermissionDenied {
+    Error::PermissionDenied { pid }
+  } else {
+    Error::Io(e)
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,53 @@
+//! Resolution of standard pidfile locations.
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::sys;
+
+/// Resolve the standard location of the pidfile for the application `app`.
+///
+/// The pidfile is named `<app>.pid` and is placed in the first of these
+/// directories which applies:
+///
+/// 1. `$RUNTIME_DIRECTORY`, as set by systemd's `RuntimeDirectory=`.  If it
+///    contains multiple directories the first one is used.
+/// 2. `/run`, or `/var/run` if `/run` does not exist, if the process is
+///    running as root.
+/// 3. `$XDG_RUNTIME_DIR`, for user services.
+/// 4. The system's temporary directory.
+///
+/// ```no_run
+/// use qpidfile::{pidfile_path, Pidfile};
+///
+/// let fname = pidfile_path("myserver");
+/// let pidfile = Pidfile::new(&fname).expect("no pidfile");
+/// ```
+pub fn pidfile_path(app: &str) -> PathBuf {
+  runtime_dir().join(format!("{}.pid", app))
+}
+
+fn runtime_dir() -> PathBuf {
+  if let Some(dir) = env_dir("RUNTIME_DIRECTORY") {
+    return dir;
+  }
+  if sys::is_root() {
+    for dir in &["/run", "/var/run"] {
+      if Path::new(dir).is_dir() {
+        return PathBuf::from(dir);
+      }
+    }
+  }
+  if let Some(dir) = env_dir("XDG_RUNTIME_DIR") {
+    return dir;
+  }
+  env::temp_dir()
+}
+
+/// Get the first absolute directory in the colon-separated list in the
+/// environment variable `name`.
+fn env_dir(name: &str) -> Option<PathBuf> {
+  let val = env::var_os(name)?;
+  env::split_paths(&val).find(|p| p.is_absolute())
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
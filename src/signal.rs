@@ -0,0 +1,148 @@
+//! Signals which can be sent to the process named in a pidfile.
+use std::os::raw::c_int;
+
+/// A signal to send to a process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Signal {
+  Hup,
+  Int,
+  Quit,
+  Kill,
+  Usr1,
+  Usr2,
+  Term,
+  Cont,
+  Stop
+}
+
+#[cfg(not(any(
+  target_os = "linux",
+  target_os = "android",
+  target_os = "solaris",
+  target_os = "illumos",
+  target_os = "macos",
+  target_os = "ios",
+  target_os = "freebsd",
+  target_os = "dragonfly",
+  target_os = "openbsd",
+  target_os = "netbsd"
+)))]
+compile_error!("signal numbers are not known for the target platform");
+
+/// All the signals, used for looking them up by name or number.
+const SIGNALS: [(Signal, &str); 9] = [
+  (Signal::Hup, "HUP"),
+  (Signal::Int, "INT"),
+  (Signal::Quit, "QUIT"),
+  (Signal::Kill, "KILL"),
+  (Signal::Usr1, "USR1"),
+  (Signal::Usr2, "USR2"),
+  (Signal::Term, "TERM"),
+  (Signal::Cont, "CONT"),
+  (Signal::Stop, "STOP")
+];
+
+impl Signal {
+  /// Look up a signal by its name, with or without the `SIG` prefix and in
+  /// any case, or by its number.
+  ///
+  /// ```
+  /// use qpidfile::Signal;
+  ///
+  /// assert_eq!(Signal::from_name("hup"), Some(Signal::Hup));
+  /// assert_eq!(Signal::from_name("SIGTERM"), Some(Signal::Term));
+  /// assert_eq!(Signal::from_name("9"), Some(Signal::Kill));
+  /// ```
+  pub fn from_name(name: &str) -> Option<Self> {
+    let upper = name.to_ascii_uppercase();
+    let upper = upper.strip_prefix("SIG").unwrap_or(&upper);
+    let num = name.parse::<c_int>().ok();
+    SIGNALS
+      .iter()
+      .find(|(sig, n)| *n == upper || Some(sig.as_raw()) == num)
+      .map(|(sig, _)| *sig)
+  }
+
+  /// The platform's number for the signal.
+  #[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    not(any(
+      target_arch = "mips",
+      target_arch = "mips64",
+      target_arch = "mips32r6",
+      target_arch = "mips64r6",
+      target_arch = "sparc",
+      target_arch = "sparc64"
+    ))
+  ))]
+  pub fn as_raw(self) -> c_int {
+    match self {
+      Signal::Hup => 1,
+      Signal::Int => 2,
+      Signal::Quit => 3,
+      Signal::Kill => 9,
+      Signal::Usr1 => 10,
+      Signal::Usr2 => 12,
+      Signal::Term => 15,
+      Signal::Cont => 18,
+      Signal::Stop => 19
+    }
+  }
+
+  /// The platform's number for the signal.
+  #[cfg(any(
+    all(
+      any(target_os = "linux", target_os = "android"),
+      any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "mips32r6",
+        target_arch = "mips64r6"
+      )
+    ),
+    target_os = "solaris",
+    target_os = "illumos"
+  ))]
+  pub fn as_raw(self) -> c_int {
+    match self {
+      Signal::Hup => 1,
+      Signal::Int => 2,
+      Signal::Quit => 3,
+      Signal::Kill => 9,
+      Signal::Usr1 => 16,
+      Signal::Usr2 => 17,
+      Signal::Term => 15,
+      Signal::Cont => 25,
+      Signal::Stop => 23
+    }
+  }
+
+  /// The platform's number for the signal.
+  #[cfg(any(
+    all(
+      any(target_os = "linux", target_os = "android"),
+      any(target_arch = "sparc", target_arch = "sparc64")
+    ),
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+  ))]
+  pub fn as_raw(self) -> c_int {
+    match self {
+      Signal::Hup => 1,
+      Signal::Int => 2,
+      Signal::Quit => 3,
+      Signal::Kill => 9,
+      Signal::Usr1 => 30,
+      Signal::Usr2 => 31,
+      Signal::Term => 15,
+      Signal::Cont => 19,
+      Signal::Stop => 17
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,219 @@
+//! Running a program which can not manage its own pidfile.
+use std::os::raw::c_int;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::{sys, Error, PidfileBuilder, Signal};
+
+/// Signals which are forwarded to the child process.
+const FORWARDED: [Signal; 6] = [
+  Signal::Hup,
+  Signal::Int,
+  Signal::Quit,
+  Signal::Term,
+  Signal::Usr1,
+  Signal::Usr2
+];
+
+/// The child process signals are forwarded to, or `0` if there is none.
+static CHILD: AtomicU32 = AtomicU32::new(0);
+
+/// Signals received before the child was spawned, as a bit mask indexed by
+/// signal number.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// Options used by [`run()`].
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+  opts: PidfileBuilder,
+  child_pid: bool,
+  forward: bool
+}
+
+impl Default for RunOptions {
+  fn default() -> Self {
+    RunOptions {
+      opts: PidfileBuilder::new(),
+      child_pid: true,
+      forward: true
+    }
+  }
+}
+
+impl RunOptions {
+  /// Create options which write the child's pid to the pidfile and forward
+  /// signals to it.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Options used to create the pidfile.
+  pub fn pidfile_options(&mut self, opts: PidfileBuilder) -> &mut Self {
+    self.opts = opts;
+    self
+  }
+
+  /// Whether to write the child's pid to the pidfile, rather than the pid of
+  /// the current process.
+  pub fn write_child_pid(&mut self, child: bool) -> &mut Self {
+    self.child_pid = child;
+    self
+  }
+
+  /// Whether to forward `SIGHUP`, `SIGINT`, `SIGQUIT`, `SIGTERM`, `SIGUSR1`
+  /// and `SIGUSR2` received by the current process to the child.
+  pub fn forward_signals(&mut self, forward: bool) -> &mut Self {
+    self.forward = forward;
+    self
+  }
+}
+
+/// Create the pidfile `fname`, run `cmd` and remove the pidfile once it has
+/// exited.  Returns the exit status of `cmd`.
+///
+/// The pidfile is created, and locked, before the command is spawned, so the
+/// command is not run if the pidfile belongs to another live process.  It is
+/// then rewritten with the child's pid, unless
+/// [`RunOptions::write_child_pid()`] is disabled.  If it can't be rewritten
+/// the child is killed and the error is returned.
+///
+/// Signals forwarded to the child replace any handlers the current process
+/// has installed for them, such as those installed by
+/// [`cleanup::install_handlers()`], until the child has exited.  Signals the
+/// current process ignores are not forwarded.  Note that signals sent to the
+/// whole process group, like the `SIGINT` generated by a terminal, reach the
+/// child both directly and through the current process.  Signals which can
+/// not be forwarded, because the child could not be spawned or has already
+/// exited, are delivered to the current process once the pidfile has been
+/// removed.  Only one command should be run at a time.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use qpidfile::RunOptions;
+///
+/// let status = qpidfile::run(
+///   "/run/legacy-server.pid",
+///   &mut Command::new("./legacy-server"),
+///   &RunOptions::new()
+/// )
+/// .expect("unable to run server");
+/// std::process::exit(status.code().unwrap_or(1));
+/// ```
+///
+/// [`cleanup::install_handlers()`]: crate::cleanup::install_handlers
+pub fn run<P: AsRef<Path>>(
+  fname: P,
+  cmd: &mut Command,
+  opts: &RunOptions
+) -> Result<ExitStatus, Error> {
+  let res = run_child(fname.as_ref(), cmd, opts);
+  // Signals which arrived while there was no child to forward them to, for
+  // instance because it could not be spawned or had already exited, are
+  // delivered to the current process now that its own dispositions have
+  // been restored and the pidfile has been removed.
+  raise_pending();
+  res
+}
+
+fn run_child(
+  fname: &Path,
+  cmd: &mut Command,
+  opts: &RunOptions
+) -> Result<ExitStatus, Error> {
+  let mut pidfile = opts.opts.create(fname)?;
+  let forwarding = if opts.forward {
+    Some(Forwarding::install()?)
+  } else {
+    None
+  };
+
+  let mut child = cmd.spawn()?;
+  CHILD.store(child.id(), Ordering::SeqCst);
+  forward_pending();
+  if opts.child_pid {
+    if let Err(e) = pidfile.set_child_pid(child.id()) {
+      CHILD.store(0, Ordering::SeqCst);
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(e);
+    }
+  }
+  let status = child.wait();
+  CHILD.store(0, Ordering::SeqCst);
+  drop(forwarding);
+  drop(pidfile);
+  Ok(status?)
+}
+
+/// Signal handlers which forward signals to the child; the previous
+/// dispositions are restored when dropped.
+struct Forwarding {
+  prev: Vec<(c_int, usize)>
+}
+
+impl Forwarding {
+  fn install() -> Result<Self, Error> {
+    let handler = forward as extern "C" fn(c_int) as usize;
+    let mut fwd = Forwarding { prev: Vec::new() };
+    PENDING.store(0, Ordering::SeqCst);
+    for sig in &FORWARDED {
+      let sig = sig.as_raw();
+      let prev = sys::set_signal_handler(sig, handler)?;
+      if prev == sys::SIG_IGN {
+        sys::set_signal_handler(sig, sys::SIG_IGN)?;
+      } else {
+        fwd.prev.push((sig, prev));
+      }
+    }
+    Ok(fwd)
+  }
+}
+
+impl Drop for Forwarding {
+  fn drop(&mut self) {
+    for &(sig, prev) in &self.prev {
+      let _ = sys::set_signal_handler(sig, prev);
+    }
+  }
+}
+
+extern "C" fn forward(sig: c_int) {
+  let pid = CHILD.load(Ordering::SeqCst);
+  if pid != 0 {
+    let _ = sys::send_signal(pid, sig);
+    return;
+  }
+  // Hold on to the signal until the child has been spawned.  It may have
+  // been spawned since CHILD was loaded, in which case forward_pending()
+  // may already have run.
+  PENDING.fetch_or(1 << sig, Ordering::SeqCst);
+  if CHILD.load(Ordering::SeqCst) != 0 {
+    forward_pending();
+  }
+}
+
+/// Forward the signals received before the child was spawned to it.
+/// Async-signal-safe.
+fn forward_pending() {
+  let pid = CHILD.load(Ordering::SeqCst);
+  let pending = PENDING.swap(0, Ordering::SeqCst);
+  for sig in 0..64 {
+    if pending & (1 << sig) != 0 {
+      let _ = sys::send_signal(pid, sig);
+    }
+  }
+}
+
+/// Deliver the signals which were not forwarded to the child to the current
+/// process.
+fn raise_pending() {
+  let pending = PENDING.swap(0, Ordering::SeqCst);
+  for sig in 0..64 {
+    if pending & (1 << sig) != 0 {
+      sys::raise_signal(sig);
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,482 @@
+//! Minimal bindings to the parts of the C library the crate needs.
+use std::fs::File;
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::io::FromRawFd;
+
+pub const LOCK_EX: c_int = 2;
+pub const LOCK_NB: c_int = 4;
+
+pub const ESRCH: c_int = 3;
+
+const O_RDONLY: c_int = 0;
+
+/// How JSON pidfiles start; the pid follows.
+const JSON_PID: &[u8] = b"{\"pid\":";
+
+#[allow(non_camel_case_types)]
+type pid_t = i32;
+
+#[cfg(any(
+  target_os = "macos",
+  target_os = "ios",
+  target_os = "freebsd",
+  target_os = "dragonfly"
+))]
+#[allow(non_camel_case_types)]
+type mode_t = u16;
+
+#[cfg(not(any(
+  target_os = "macos",
+  target_os = "ios",
+  target_os = "freebsd",
+  target_os = "dragonfly"
+)))]
+#[allow(non_camel_case_types)]
+type mode_t = u32;
+
+extern "C" {
+  fn flock(fd: c_int, operation: c_int) -> c_int;
+  fn kill(pid: pid_t, sig: c_int) -> c_int;
+  fn fork() -> pid_t;
+  fn setsid() -> pid_t;
+  fn _exit(status: c_int) -> !;
+  fn pipe(fds: *mut c_int) -> c_int;
+  fn dup2(oldfd: c_int, newfd: c_int) -> c_int;
+  fn umask(mask: mode_t) -> mode_t;
+  fn waitpid(pid: pid_t, status: *mut c_int, options: c_int) -> pid_t;
+  fn gethostname(name: *mut c_char, len: usize) -> c_int;
+  fn geteuid() -> u32;
+  fn getpid() -> pid_t;
+  #[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+  fn signal(signum: c_int, handler: usize) -> usize;
+  fn raise(sig: c_int) -> c_int;
+  fn unlink(path: *const c_char) -> c_int;
+  fn open(path: *const c_char, flags: c_int, ...) -> c_int;
+  fn read(fd: c_int, buf: *mut u8, count: usize) -> isize;
+  fn close(fd: c_int) -> c_int;
+  fn atexit(cb: extern "C" fn()) -> c_int;
+}
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+#[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+const SIG_ERR: usize = !0;
+
+/// `signal()` has System V semantics on illumos and Solaris; the handler is
+/// reset when it runs and interrupted system calls are not restarted.  Use
+/// `sigaction()` to get the BSD semantics `signal()` has elsewhere.
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+mod sunos {
+  use std::io;
+  use std::os::raw::{c_int, c_uint};
+
+  const SA_RESTART: c_int = 0x4;
+
+  #[repr(C)]
+  struct SigAction {
+    sa_flags: c_int,
+    sa_handler: usize,
+    sa_mask: [c_uint; 4],
+    #[cfg(target_pointer_width = "32")]
+    _resv: [c_int; 2]
+  }
+
+  extern "C" {
+    fn sigaction(
+      sig: c_int,
+      act: *const SigAction,
+      oact: *mut SigAction
+    ) -> c_int;
+  }
+
+  pub fn set_signal_handler(sig: c_int, handler: usize) -> io::Result<usize> {
+    let act = SigAction {
+      sa_flags: SA_RESTART,
+      sa_handler: handler,
+      sa_mask: [0; 4],
+      #[cfg(target_pointer_width = "32")]
+      _resv: [0; 2]
+    };
+    let mut old = std::mem::MaybeUninit::<SigAction>::zeroed();
+    if unsafe { sigaction(sig, &act, old.as_mut_ptr()) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { old.assume_init() }.sa_handler)
+  }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+  use std::ffi::CStr;
+  use std::io;
+  use std::os::raw::{
+    c_char, c_int, c_long, c_short, c_uint, c_ulong, c_void
+  };
+  use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+  use std::ptr;
+  use std::sync::atomic::{AtomicPtr, Ordering};
+  use std::sync::Once;
+
+  /// Offset which some ABIs add to the syscall numbers shared by all
+  /// architectures since Linux 5.1.
+  #[cfg(any(target_arch = "mips", target_arch = "mips32r6"))]
+  const SYS_BASE: c_long = 4000;
+  #[cfg(all(
+    any(target_arch = "mips64", target_arch = "mips64r6"),
+    target_pointer_width = "64"
+  ))]
+  const SYS_BASE: c_long = 5000;
+  #[cfg(all(
+    any(target_arch = "mips64", target_arch = "mips64r6"),
+    target_pointer_width = "32"
+  ))]
+  const SYS_BASE: c_long = 6000;
+  #[cfg(all(target_arch = "x86_64", target_pointer_width = "32"))]
+  const SYS_BASE: c_long = 0x4000_0000;
+  #[cfg(not(any(
+    target_arch = "mips",
+    target_arch = "mips32r6",
+    target_arch = "mips64",
+    target_arch = "mips64r6",
+    all(target_arch = "x86_64", target_pointer_width = "32")
+  )))]
+  const SYS_BASE: c_long = 0;
+
+  const SYS_PIDFD_SEND_SIGNAL: c_long = SYS_BASE + 424;
+  const SYS_PIDFD_OPEN: c_long = SYS_BASE + 434;
+
+  const POLLIN: c_short = 0x1;
+
+  const AT_FDCWD: c_int = -100;
+  const STATX_INO: c_uint = 0x100;
+
+  /// `struct statx`, whose layout is the same on all architectures.
+  #[repr(C)]
+  struct Statx {
+    mask: u32,
+    blksize: u32,
+    attributes: u64,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    mode: u16,
+    _pad0: u16,
+    ino: u64,
+    size: u64,
+    blocks: u64,
+    attributes_mask: u64,
+    _times: [u64; 8],
+    rdev_major: u32,
+    rdev_minor: u32,
+    dev_major: u32,
+    dev_minor: u32,
+    _spare: [u64; 14]
+  }
+
+  #[repr(C)]
+  struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short
+  }
+
+  extern "C" {
+    fn syscall(num: c_long, ...) -> c_long;
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+  }
+
+  type StatxFn = unsafe extern "C" fn(
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mask: c_uint,
+    buf: *mut Statx
+  ) -> c_int;
+
+  /// `statx()`, if the C library provides it; glibc only does since 2.28.
+  static STATX: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+  static RESOLVE_STATX: Once = Once::new();
+
+  /// Look up `statx()` in the C library.  Not async-signal-safe, so this
+  /// must be called before [`file_id()`] can be used.
+  pub fn resolve_statx() {
+    RESOLVE_STATX.call_once(|| {
+      let name = b"statx\0".as_ptr() as *const c_char;
+      // A null handle is RTLD_DEFAULT in both glibc and musl.
+      let sym = unsafe { dlsym(ptr::null_mut(), name) };
+      STATX.store(sym, Ordering::SeqCst);
+    });
+  }
+
+  /// Get the device and inode numbers of the file `path`, encoded the same
+  /// way as by [`std::fs::Metadata`].  Returns `Ok(None)` if `statx()` is
+  /// unavailable, or hasn't been looked up by [`resolve_statx()`].
+  /// Async-signal-safe.
+  pub fn file_id(path: &CStr) -> io::Result<Option<(u64, u64)>> {
+    let sym = STATX.load(Ordering::SeqCst);
+    if sym.is_null() {
+      return Ok(None);
+    }
+    // SAFETY: The symbol is the C library's statx().
+    let statx = unsafe { std::mem::transmute::<*mut c_void, StatxFn>(sym) };
+    let mut buf = std::mem::MaybeUninit::<Statx>::zeroed();
+    let ptr = buf.as_mut_ptr();
+    let ret = unsafe { statx(AT_FDCWD, path.as_ptr(), 0, STATX_INO, ptr) };
+    if ret != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let buf = unsafe { buf.assume_init() };
+    let (major, minor) = (buf.dev_major as u64, buf.dev_minor as u64);
+    let dev = ((major & 0x0000_0fff) << 8)
+      | ((major & 0xffff_f000) << 32)
+      | (minor & 0x0000_00ff)
+      | ((minor & 0xffff_ff00) << 12);
+    Ok(Some((dev, buf.ino)))
+  }
+
+  /// Obtain a file descriptor referring to the process `pid`.
+  pub fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+    let ret = unsafe { syscall(SYS_PIDFD_OPEN, pid as c_int, 0 as c_uint) };
+    if ret < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(ret as RawFd) })
+  }
+
+  /// Send the signal `sig` to the process referred to by `pidfd`.
+  pub fn pidfd_send_signal(pidfd: RawFd, sig: c_int) -> io::Result<()> {
+    let ret = unsafe {
+      syscall(
+        SYS_PIDFD_SEND_SIGNAL,
+        pidfd,
+        sig,
+        ptr::null_mut::<u8>(),
+        0 as c_uint
+      )
+    };
+    if ret < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  /// Wait for `fd` to become readable, for at most `timeout` milliseconds
+  /// (`-1` meaning indefinitely).  Returns `Ok(false)` on timeout.
+  pub fn poll_readable(fd: RawFd, timeout: c_int) -> io::Result<bool> {
+    let mut pfd = PollFd {
+      fd,
+      events: POLLIN,
+      revents: 0
+    };
+    let ret = unsafe { poll(&mut pfd, 1, timeout) };
+    if ret < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(ret > 0)
+  }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::*;
+
+fn cvt(ret: c_int) -> io::Result<c_int> {
+  if ret == -1 {
+    Err(io::Error::last_os_error())
+  } else {
+    Ok(ret)
+  }
+}
+
+fn cvt_r<F: FnMut() -> c_int>(mut f: F) -> io::Result<c_int> {
+  loop {
+    match cvt(f()) {
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+      res => return res
+    }
+  }
+}
+
+/// Attempt to acquire an exclusive lock on `fd` without blocking.
+///
+/// Returns `Ok(false)` if the lock is held by someone else.
+pub fn try_lock_exclusive(fd: c_int) -> io::Result<bool> {
+  loop {
+    if unsafe { flock(fd, LOCK_EX | LOCK_NB) } == 0 {
+      return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.kind() {
+      io::ErrorKind::Interrupted => continue,
+      io::ErrorKind::WouldBlock => return Ok(false),
+      _ => return Err(err)
+    }
+  }
+}
+
+/// Check whether a process with the identifier `pid` exists.
+///
+/// A process which exists, but which we lack permission to signal, is
+/// considered to be alive.
+pub fn is_alive(pid: u32) -> io::Result<bool> {
+  if unsafe { kill(pid as pid_t, 0) } == 0 {
+    return Ok(true);
+  }
+  let err = io::Error::last_os_error();
+  match err.kind() {
+    io::ErrorKind::PermissionDenied => Ok(true),
+    _ if err.raw_os_error() == Some(ESRCH) => Ok(false),
+    _ => Err(err)
+  }
+}
+
+/// Send the signal `sig` to the process `pid`.  Async-signal-safe.
+pub fn send_signal(pid: u32, sig: c_int) -> io::Result<()> {
+  cvt(unsafe { kill(pid as pid_t, sig) }).map(|_| ())
+}
+
+/// Get the pid of the calling process.  Async-signal-safe.
+pub fn current_pid() -> u32 {
+  unsafe { getpid() as u32 }
+}
+
+/// Set the disposition of the signal `sig` to `handler`, which is either
+/// [`SIG_DFL`], [`SIG_IGN`] or the address of an `extern "C" fn(c_int)`.
+/// Returns the previous disposition.
+///
+/// The handler stays installed after it has run, and system calls
+/// interrupted by the signal are restarted.
+#[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+pub fn set_signal_handler(sig: c_int, handler: usize) -> io::Result<usize> {
+  match unsafe { signal(sig, handler) } {
+    SIG_ERR => Err(io::Error::last_os_error()),
+    prev => Ok(prev)
+  }
+}
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+pub use sunos::set_signal_handler;
+
+/// Send the signal `sig` to the calling thread.  Async-signal-safe.
+pub fn raise_signal(sig: c_int) {
+  unsafe {
+    raise(sig);
+  }
+}
+
+/// Remove the file `path`, ignoring any errors.  Async-signal-safe.
+pub fn unlink_quietly(path: &std::ffi::CStr) {
+  unsafe {
+    unlink(path.as_ptr());
+  }
+}
+
+/// Read the pid on the first line of the file `path`, or at the start of a
+/// JSON pidfile, returning `None` if it can't be read or doesn't start with a
+/// pid.  Async-signal-safe.
+pub fn read_pid_quietly(path: &std::ffi::CStr) -> Option<u32> {
+  let fd = unsafe { open(path.as_ptr(), O_RDONLY) };
+  if fd < 0 {
+    return None;
+  }
+  let mut buf = [0u8; 32];
+  let len = unsafe { read(fd, buf.as_mut_ptr(), buf.len()) };
+  unsafe {
+    close(fd);
+  }
+  if len <= 0 {
+    return None;
+  }
+  let len = len as usize;
+  let start = buf[..len].iter().position(|b| !b.is_ascii_whitespace())?;
+  let json = buf[start..len].starts_with(JSON_PID);
+  let start = if json { start + JSON_PID.len() } else { start };
+  let mut pid: u32 = 0;
+  for &b in &buf[start..len] {
+    match b {
+      b'0'..=b'9' => {
+        pid = pid.checked_mul(10)?.checked_add((b - b'0') as u32)?;
+      }
+      b if b.is_ascii_whitespace() && !json => return Some(pid),
+      b',' | b'}' if json => return Some(pid),
+      _ => return None
+    }
+  }
+  // The whole file was read, unless the buffer was filled; in which case
+  // the pid may have been cut off.
+  if len < buf.len() && !json {
+    Some(pid)
+  } else {
+    None
+  }
+}
+
+/// Register `cb` to be called when the process exits.
+pub fn at_exit(cb: extern "C" fn()) -> io::Result<()> {
+  if unsafe { atexit(cb) } != 0 {
+    return Err(io::Error::other("unable to register exit handler"));
+  }
+  Ok(())
+}
+
+/// Check whether the process' effective user is root.
+pub fn is_root() -> bool {
+  unsafe { geteuid() == 0 }
+}
+
+/// Get the name of the host.
+pub fn hostname() -> io::Result<String> {
+  let mut buf = [0u8; 256];
+  cvt(unsafe { gethostname(buf.as_mut_ptr() as *mut c_char, buf.len()) })?;
+  let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+  Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Fork the current process.  Returns `0` in the child and the child's pid in
+/// the parent.
+///
+/// # Safety
+/// The child may only use async-signal-safe functions if the parent has
+/// multiple threads.
+pub unsafe fn fork_process() -> io::Result<i32> {
+  cvt(fork())
+}
+
+/// Create a new session, with the calling process as its leader.
+pub fn new_session() -> io::Result<()> {
+  cvt(unsafe { setsid() }).map(|_| ())
+}
+
+/// Terminate the calling process immediately, without running any exit
+/// handlers.
+pub fn exit_immediately(status: i32) -> ! {
+  unsafe { _exit(status) }
+}
+
+/// Create a pipe, returning its read and write ends.
+pub fn create_pipe() -> io::Result<(File, File)> {
+  let mut fds: [c_int; 2] = [-1, -1];
+  cvt(unsafe { pipe(fds.as_mut_ptr()) })?;
+  unsafe { Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))) }
+}
+
+/// Make `newfd` refer to the same open file as `oldfd`.
+pub fn dup_onto(oldfd: c_int, newfd: c_int) -> io::Result<()> {
+  cvt_r(|| unsafe { dup2(oldfd, newfd) }).map(|_| ())
+}
+
+/// Set the file mode creation mask, returning the previous mask.
+pub fn set_umask(mask: u32) -> u32 {
+  unsafe { umask(mask as mode_t) as u32 }
+}
+
+/// Wait for the child process `pid` to change state, returning its raw wait
+/// status.
+pub fn wait_child(pid: i32) -> io::Result<i32> {
+  let mut status: c_int = 0;
+  cvt_r(|| unsafe { waitpid(pid, &mut status, 0) })?;
+  Ok(status)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
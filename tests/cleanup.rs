@@ -0,0 +1,80 @@
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use qpidfile::{cleanup, Pidfile, Signal};
+
+mod common;
+
+use common::pidfile_name;
+
+/// Set in the environment of the child process, to how it should terminate.
+const CHILD_ENV: &str = "QPIDFILE_TEST_CLEANUP";
+
+/// Set in the environment of the child process, to the pidfile to create.
+const PIDFILE_ENV: &str = "QPIDFILE_TEST_PIDFILE";
+
+/// The body of the child process, which does nothing unless it is run by
+/// [`run_child()`].  It creates a pidfile, replaces it with another file
+/// naming the same process, and terminates through the cleanup handlers.
+#[test]
+fn child() {
+  let how = match std::env::var(CHILD_ENV) {
+    Ok(how) => how,
+    Err(_) => return
+  };
+  let fname = PathBuf::from(std::env::var_os(PIDFILE_ENV).unwrap());
+  cleanup::install_handlers().expect("unable to install handlers");
+  let pidfile = Pidfile::new(&fname).expect("unable to create pidfile");
+  assert!(pidfile.is_registered());
+
+  let tmp = fname.with_extension("tmp");
+  std::fs::write(&tmp, format!("{}\n", std::process::id())).unwrap();
+  std::fs::rename(&tmp, &fname).unwrap();
+
+  match how.as_str() {
+    // Neither runs the pidfile's destructor.
+    "exit" => std::process::exit(0),
+    "signal" => {
+      Pidfile::signal(&fname, Signal::Term).expect("unable to signal");
+      loop {
+        std::thread::sleep(Duration::from_secs(1));
+      }
+    }
+    _ => panic!("unknown termination {:?}", how)
+  }
+}
+
+/// Run [`child()`] in a new process which terminates as described by `how`,
+/// and return the pidfile it created along with its pid and exit status.
+fn run_child(how: &str) -> (PathBuf, u32, std::process::ExitStatus) {
+  let fname = pidfile_name(how);
+  let mut child = Command::new(std::env::current_exe().unwrap())
+    .args(["--exact", "child", "--test-threads=1"])
+    .env(CHILD_ENV, how)
+    .env(PIDFILE_ENV, &fname)
+    .stdout(Stdio::null())
+    .spawn()
+    .expect("unable to spawn");
+  let status = child.wait().unwrap();
+  (fname, child.id(), status)
+}
+
+#[test]
+fn replaced_pidfile_survives_exit() {
+  let (fname, pid, status) = run_child("exit");
+  assert!(status.success(), "{:?}", status);
+  assert_eq!(std::fs::read_to_string(&fname).unwrap(), format!("{}\n", pid));
+  std::fs::remove_file(&fname).unwrap();
+}
+
+#[test]
+fn replaced_pidfile_survives_signal() {
+  let (fname, pid, status) = run_child("signal");
+  assert_eq!(status.signal(), Some(Signal::Term.as_raw()), "{:?}", status);
+  assert_eq!(std::fs::read_to_string(&fname).unwrap(), format!("{}\n", pid));
+  std::fs::remove_file(&fname).unwrap();
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,69 @@
+#![cfg(feature = "cli")]
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use qpidfile::Pidfile;
+
+mod common;
+
+use common::pidfile_name;
+
+/// Run `qpidfile status` on `fname`, and return its exit code.
+fn status(fname: &Path) -> Option<i32> {
+  Command::new(env!("CARGO_BIN_EXE_qpidfile"))
+    .arg("status")
+    .arg(fname)
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .expect("unable to run qpidfile")
+    .code()
+}
+
+#[test]
+fn status_running() {
+  let fname = pidfile_name("running");
+  let pidfile = Pidfile::new(&fname).expect("unable to create pidfile");
+  assert_eq!(status(&fname), Some(0));
+  drop(pidfile);
+}
+
+#[test]
+fn status_dead() {
+  let fname = pidfile_name("dead");
+  let mut child = Command::new("true").spawn().expect("unable to spawn");
+  child.wait().expect("unable to wait");
+  std::fs::write(&fname, format!("{}\n", child.id())).unwrap();
+  assert_eq!(status(&fname), Some(1));
+  std::fs::remove_file(&fname).unwrap();
+}
+
+#[test]
+fn status_not_running() {
+  let fname = pidfile_name("missing");
+  assert_eq!(status(&fname), Some(3));
+}
+
+#[test]
+fn status_unknown() {
+  let fname = pidfile_name("malformed");
+  std::fs::write(&fname, "not a pid\n").unwrap();
+  assert_eq!(status(&fname), Some(4));
+  std::fs::remove_file(&fname).unwrap();
+}
+
+#[test]
+fn invalid_timeout_is_usage_error() {
+  let fname = pidfile_name("timeout");
+  let code = Command::new(env!("CARGO_BIN_EXE_qpidfile"))
+    .args(["wait", "-t", "1e300"])
+    .arg(&fname)
+    .stderr(Stdio::null())
+    .status()
+    .expect("unable to run qpidfile")
+    .code();
+  assert_eq!(code, Some(2));
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
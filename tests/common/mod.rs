@@ -0,0 +1,12 @@
+//! Helpers shared by the integration tests.
+use std::path::PathBuf;
+
+/// A path for the pidfile of the test `name`, which does not exist yet.
+pub fn pidfile_name(name: &str) -> PathBuf {
+  let fname = std::env::temp_dir()
+    .join(format!("qpidfile-{}-{}.pid", name, std::process::id()));
+  let _ = std::fs::remove_file(&fname);
+  fname
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
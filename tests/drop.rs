@@ -0,0 +1,61 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use qpidfile::{Error, Pidfile};
+
+mod common;
+
+use common::pidfile_name;
+
+/// The drop error hook is process-wide, so only one test may use it at a
+/// time.
+static HOOK: Mutex<()> = Mutex::new(());
+
+/// Drop `pidfile`, and return whether the drop error hook was told that the
+/// pidfile `fname` no longer belongs to it.
+fn drop_not_owner(pidfile: Pidfile, fname: &Path) -> bool {
+  let _guard = HOOK.lock().unwrap_or_else(|e| e.into_inner());
+  let reported = Arc::new(Mutex::new(false));
+  let flag = Arc::clone(&reported);
+  let expected = fname.to_path_buf();
+  qpidfile::set_drop_error_hook(move |fname, err| {
+    if fname == expected && matches!(err, Error::NotOwner(p) if *p == expected)
+    {
+      *flag.lock().unwrap() = true;
+    }
+  });
+  drop(pidfile);
+  qpidfile::clear_drop_error_hook();
+  let reported = *reported.lock().unwrap();
+  reported
+}
+
+#[test]
+fn replaced_pidfile_is_left_alone() {
+  let fname = pidfile_name("replaced");
+  let pidfile = Pidfile::new(&fname).expect("unable to create pidfile");
+  let tmp = fname.with_extension("tmp");
+  std::fs::write(&tmp, "1\n").unwrap();
+  std::fs::rename(&tmp, &fname).unwrap();
+
+  assert!(drop_not_owner(pidfile, &fname));
+  assert_eq!(std::fs::read_to_string(&fname).unwrap(), "1\n");
+  std::fs::remove_file(&fname).unwrap();
+}
+
+#[test]
+fn rewritten_pidfile_is_left_alone() {
+  use std::os::unix::fs::MetadataExt;
+
+  let fname = pidfile_name("rewritten");
+  let pidfile = Pidfile::new(&fname).expect("unable to create pidfile");
+  let ino = std::fs::metadata(&fname).unwrap().ino();
+  std::fs::write(&fname, "1\n").unwrap();
+  assert_eq!(std::fs::metadata(&fname).unwrap().ino(), ino);
+
+  assert!(drop_not_owner(pidfile, &fname));
+  assert_eq!(std::fs::read_to_string(&fname).unwrap(), "1\n");
+  std::fs::remove_file(&fname).unwrap();
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,80 @@
+#![cfg(feature = "serde")]
+
+use serde::{Deserialize, Serialize};
+
+use qpidfile::{Error, Pidfile, PidfileBuilder, PidfileInfo};
+
+mod common;
+
+use common::pidfile_name;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct State {
+  port: u16,
+  peers: Vec<String>
+}
+
+fn state() -> State {
+  State {
+    port: 8080,
+    peers: vec!["a".to_string(), "b".to_string()]
+  }
+}
+
+#[test]
+fn state_round_trip() {
+  let fname = pidfile_name("round-trip");
+  let pidfile = PidfileBuilder::new()
+    .version("1.0")
+    .metadata("role", "primary")
+    .json_state(&state())
+    .create(&fname)
+    .expect("unable to create pidfile");
+
+  let contents = std::fs::read_to_string(&fname).unwrap();
+  let prefix = format!("{{\"pid\":{},", std::process::id());
+  assert!(contents.starts_with(&prefix), "{}", contents);
+  assert_eq!(contents.lines().count(), 1);
+
+  let (info, read) = PidfileInfo::read_json::<State, _>(&fname).unwrap();
+  assert_eq!(info.pid(), std::process::id());
+  assert_eq!(info.version(), Some("1.0"));
+  assert_eq!(info.get("role"), Some("primary"));
+  assert_eq!(read, state());
+  assert!(info.is_alive().unwrap());
+
+  drop(pidfile);
+  assert!(!fname.exists());
+}
+
+#[test]
+fn json_pidfile_is_locked() {
+  let fname = pidfile_name("locked");
+  let _pidfile = PidfileBuilder::new()
+    .json_state(&state())
+    .create(&fname)
+    .expect("unable to create pidfile");
+  match Pidfile::new(&fname) {
+    Err(Error::AlreadyRunning { pid }) => assert_eq!(pid, std::process::id()),
+    res => panic!("unexpected result {:?}", res.map(|_| ()))
+  }
+}
+
+#[test]
+fn missing_or_mistyped_state() {
+  let fname = pidfile_name("plain");
+  let _pidfile = Pidfile::new(&fname).expect("unable to create pidfile");
+  assert!(matches!(
+    PidfileInfo::read_json::<State, _>(&fname),
+    Err(Error::Parse(_))
+  ));
+
+  let info: PidfileInfo =
+    r#"{"pid":1,"state":{"port":"x"}}"#.parse().unwrap();
+  assert_eq!(info.pid(), 1);
+  assert!(matches!(info.state::<State>(), Err(Error::Parse(_))));
+  assert!("{\"pid\":0}".parse::<PidfileInfo>().is_err());
+  assert!("{\"state\":1}".parse::<PidfileInfo>().is_err());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,41 @@
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use std::time::Duration;
+
+use qpidfile::{Error, PidFd, Pidfile};
+
+mod common;
+
+use common::pidfile_name;
+
+#[test]
+fn locked_pidfile_is_verified() {
+  let fname = pidfile_name("locked");
+  let _pidfile = Pidfile::new(&fname).expect("unable to create pidfile");
+  let pidfd = PidFd::from_pidfile(&fname).expect("unable to open pidfd");
+  assert_eq!(pidfd.pid(), std::process::id());
+}
+
+#[test]
+fn unlocked_pidfile_is_stale() {
+  // Some process with pid 1 always exists, but it doesn't own the pidfile.
+  let fname = pidfile_name("unlocked");
+  std::fs::write(&fname, "1\n").unwrap();
+  let res = PidFd::from_pidfile(&fname);
+  std::fs::remove_file(&fname).unwrap();
+  match res {
+    Err(Error::Stale { pid: 1 }) => {}
+    res => panic!("unexpected result {:?}", res)
+  }
+}
+
+#[test]
+fn wait_without_deadline() {
+  let mut child = Command::new("true").spawn().expect("unable to spawn");
+  let pidfd = PidFd::open(child.id()).expect("unable to open pidfd");
+  assert!(pidfd.wait(Some(Duration::MAX)).unwrap());
+  child.wait().unwrap();
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,79 @@
+use std::process::Command;
+use std::time::Duration;
+
+use qpidfile::{wait_for_exit, Error, Pidfile, StalePolicy};
+
+mod common;
+
+use common::pidfile_name;
+
+/// The pid of a process which has exited and been reaped.
+fn dead_pid() -> u32 {
+  let mut child = Command::new("true").spawn().expect("unable to spawn");
+  child.wait().expect("unable to wait");
+  child.id()
+}
+
+#[test]
+fn second_pidfile_is_already_running() {
+  let fname = pidfile_name("locked");
+  let pidfile = Pidfile::new(&fname).expect("unable to create pidfile");
+  match Pidfile::new(&fname) {
+    Err(Error::AlreadyRunning { pid }) => assert_eq!(pid, std::process::id()),
+    res => panic!("unexpected result {:?}", res.map(|_| ()))
+  }
+  drop(pidfile);
+  assert!(!fname.exists());
+}
+
+#[test]
+fn stale_pidfile_is_reclaimed() {
+  let fname = pidfile_name("reclaim");
+  std::fs::write(&fname, format!("{}\n", dead_pid())).unwrap();
+  let pidfile = Pidfile::with_policy(&fname, StalePolicy::Reclaim)
+    .expect("unable to reclaim stale pidfile");
+  assert_eq!(Pidfile::read(&fname).unwrap().pid(), std::process::id());
+  drop(pidfile);
+  assert!(!fname.exists());
+}
+
+#[test]
+fn stale_pidfile_is_rejected() {
+  let fname = pidfile_name("reject");
+  let pid = dead_pid();
+  std::fs::write(&fname, format!("{}\n", pid)).unwrap();
+  match Pidfile::with_policy(&fname, StalePolicy::Fail) {
+    Err(Error::Stale { pid: stale }) => assert_eq!(stale, pid),
+    res => panic!("unexpected result {:?}", res.map(|_| ()))
+  }
+  // The stale pidfile is left alone.
+  assert_eq!(Pidfile::read(&fname).unwrap().pid(), pid);
+  std::fs::remove_file(&fname).unwrap();
+}
+
+#[test]
+fn live_json_pidfile_is_already_running() {
+  // JSON pidfiles are recognised whether or not the serde feature is
+  // enabled.
+  let fname = pidfile_name("json");
+  let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+  let contents = format!("{{\"pid\":{},\"metadata\":{{}}}}\n", child.id());
+  std::fs::write(&fname, &contents).unwrap();
+  let res = Pidfile::new(&fname);
+  child.kill().unwrap();
+  child.wait().unwrap();
+  match res {
+    Err(Error::AlreadyRunning { pid }) => assert_eq!(pid, child.id()),
+    res => panic!("unexpected result {:?}", res.map(|_| ()))
+  }
+  assert_eq!(std::fs::read_to_string(&fname).unwrap(), contents);
+  std::fs::remove_file(&fname).unwrap();
+}
+
+#[test]
+fn wait_for_exit_without_deadline() {
+  let fname = pidfile_name("wait");
+  assert!(wait_for_exit(&fname, Duration::MAX).unwrap());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use qpidfile::{cleanup, Pidfile};
+
+/// Runs in its own process, since the registry is process-wide.
+#[test]
+fn full_registry_is_not_recorded() {
+  let reported = Arc::new(AtomicUsize::new(0));
+  let counter = Arc::clone(&reported);
+  qpidfile::set_drop_error_hook(move |_, _| {
+    counter.fetch_add(1, Ordering::SeqCst);
+  });
+
+  let dir = std::env::temp_dir();
+  let pid = std::process::id();
+  let pidfiles: Vec<Pidfile> = (0..65)
+    .map(|n| {
+      let fname = dir.join(format!("qpidfile-registry-{}-{}.pid", n, pid));
+      Pidfile::new(fname).expect("unable to create pidfile")
+    })
+    .collect();
+
+  let registered = pidfiles.iter().filter(|p| p.is_registered()).count();
+  assert_eq!(registered, 64);
+  assert!(!pidfiles[64].is_registered());
+  assert_eq!(cleanup::pidfiles().len(), 64);
+
+  drop(pidfiles);
+  assert!(cleanup::pidfiles().is_empty());
+  assert_eq!(reported.load(Ordering::SeqCst), 0);
+  qpidfile::clear_drop_error_hook();
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
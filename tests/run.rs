@@ -0,0 +1,101 @@
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use qpidfile::{Pidfile, RunOptions, Signal};
+
+mod common;
+
+use common::pidfile_name;
+
+extern "C" {
+  fn kill(pid: i32, sig: i32) -> i32;
+}
+
+/// Set in the environment of the child process, to the pidfile to create.
+const PIDFILE_ENV: &str = "QPIDFILE_TEST_PIDFILE";
+
+/// Only one command can be run at a time, since signals are forwarded to it.
+static RUN: Mutex<()> = Mutex::new(());
+
+#[test]
+fn child_pid_and_exit_status() {
+  let _guard = RUN.lock().unwrap_or_else(|e| e.into_inner());
+  let fname = pidfile_name("status");
+  let mut cmd = Command::new("sh");
+  cmd.arg("-c").arg(format!(
+    "sleep 0.2; test \"$(head -n 1 {})\" = $$ && exit 7",
+    fname.display()
+  ));
+  let status = qpidfile::run(&fname, &mut cmd, &RunOptions::new())
+    .expect("unable to run command");
+  assert_eq!(status.code(), Some(7));
+  assert!(!fname.exists());
+}
+
+#[test]
+fn signal_before_spawn_is_forwarded() {
+  let _guard = RUN.lock().unwrap_or_else(|e| e.into_inner());
+  let fname = pidfile_name("pending");
+  let mut cmd = Command::new("sleep");
+  cmd.arg("10");
+  // Signal the current process while the spawn is underway; by then the
+  // signal handlers have been installed, since they are installed before
+  // the child is forked.  The delay makes it likely that the signal is
+  // handled before the child's pid is known.
+  unsafe {
+    cmd.pre_exec(|| {
+      kill(std::os::unix::process::parent_id() as i32, Signal::Term.as_raw());
+      thread::sleep(Duration::from_millis(500));
+      Ok(())
+    });
+  }
+
+  let start = Instant::now();
+  let status = qpidfile::run(&fname, &mut cmd, &RunOptions::new())
+    .expect("unable to run command");
+  assert_eq!(status.signal(), Some(Signal::Term.as_raw()));
+  assert!(start.elapsed() < Duration::from_secs(5));
+  assert!(Pidfile::read(&fname).is_err());
+}
+
+/// The body of the child process of [`signal_is_raised_if_spawn_fails()`],
+/// which does nothing unless it is run by it.
+#[test]
+fn spawn_failure_child() {
+  let fname = match std::env::var_os(PIDFILE_ENV) {
+    Some(fname) => PathBuf::from(fname),
+    None => return
+  };
+  // As in signal_before_spawn_is_forwarded(), but the spawn fails.
+  let mut cmd = Command::new("true");
+  unsafe {
+    cmd.pre_exec(|| {
+      kill(std::os::unix::process::parent_id() as i32, Signal::Term.as_raw());
+      thread::sleep(Duration::from_millis(500));
+      Err(io::Error::from_raw_os_error(1))
+    });
+  }
+  let res = qpidfile::run(&fname, &mut cmd, &RunOptions::new());
+  panic!("not terminated by the signal; {:?}", res);
+}
+
+#[test]
+fn signal_is_raised_if_spawn_fails() {
+  let fname = pidfile_name("spawn-failure");
+  let status = Command::new(std::env::current_exe().unwrap())
+    .args(["--exact", "spawn_failure_child", "--test-threads=1"])
+    .env(PIDFILE_ENV, &fname)
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .expect("unable to spawn");
+  assert_eq!(status.signal(), Some(Signal::Term.as_raw()), "{:?}", status);
+  assert!(!fname.exists());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :